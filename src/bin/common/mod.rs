@@ -0,0 +1,80 @@
+//! Helpers shared by the `metalcab` and `metalcab-server` binaries
+
+use getopts::{Matches, Options};
+use metalcab::compat::{MecabError, Model};
+use metalcab::tokenizer::Token;
+use serde_json::Value;
+use std::ffi::CString;
+
+/// Adds the options which select the dictionaries: `-d`, `-u` and `-r`.
+pub fn model_options(opts: &mut Options) {
+    opts.optopt("d", "dicdir", "use the system dictionary in DIR", "DIR");
+    opts.optmulti("u", "userdic", "use the user dictionary FILE", "FILE");
+    opts.optopt("r", "rcfile", "use FILE as the resource file", "FILE");
+}
+
+/// Builds the arguments of `Model::create` from the options added by `model_options`,
+/// followed by `extra`.
+pub fn model_args(
+    matches: &Matches,
+    program: &str,
+    extra: &[&str],
+) -> Result<Vec<CString>, String> {
+    let mut args = vec![program.to_string()];
+    if let Some(dicdir) = matches.opt_str("dicdir") {
+        args.push("-d".to_string());
+        args.push(dicdir);
+    }
+    let userdics = matches.opt_strs("userdic");
+    if !userdics.is_empty() {
+        args.push("-u".to_string());
+        args.push(userdics.join(","));
+    }
+    if let Some(rcfile) = matches.opt_str("rcfile") {
+        args.push("-r".to_string());
+        args.push(rcfile);
+    }
+    args.extend(extra.iter().map(|arg| arg.to_string()));
+    args.into_iter()
+        .map(|arg| CString::new(arg).map_err(|e| e.to_string()))
+        .collect()
+}
+
+pub fn load_model(args: &[CString]) -> Result<Model, MecabError> {
+    let args: Vec<_> = args.iter().map(|arg| arg.as_c_str()).collect();
+    Model::create(&args)
+}
+
+/// Parses a positive number given to `--name`.
+pub fn parse_number(matches: &Matches, name: &str, default: usize) -> Result<usize, String> {
+    match matches.opt_str(name) {
+        Some(s) => match s.parse() {
+            Ok(n) if n > 0 => Ok(n),
+            _ => Err(format!("invalid number for --{}: {}", name, s)),
+        },
+        None => Ok(default),
+    }
+}
+
+pub fn mecab_error(e: MecabError) -> String {
+    format!("MeCab error: {:?}", e)
+}
+
+pub fn tokens_json(tokens: &[Token]) -> Value {
+    Value::Array(
+        tokens
+            .iter()
+            .map(|token| {
+                json!({
+                    "surface": token.surface,
+                    "feature": token.feature,
+                    "features": token.features(),
+                    "start": token.start,
+                    "end": token.end,
+                    "posid": token.posid,
+                    "unknown": token.unknown,
+                })
+            })
+            .collect(),
+    )
+}
@@ -0,0 +1,268 @@
+//! `metalcab-server`: a local HTTP/JSON morphological analysis server
+//!
+//! Loads a model once and serves:
+//!
+//! - `POST /analyze` with `{"text": "..."}`, returning `{"text": ..., "tokens": [...]}`.
+//! - `POST /nbest` with `{"text": "...", "n": 3}`, returning `{"text": ..., "nbest": [[...]]}`.
+//!   `n` defaults to 10.
+//! - `GET /dictionary`, returning `{"dictionaries": [...]}`.
+//! - `POST /reload`, which loads the dictionaries again and swaps the model, returning the
+//!   same as `/dictionary`.
+//!
+//! Errors are returned as `{"error": "..."}`, and request bodies larger than `--max-body`
+//! are rejected with status 413. Run with `--help` for the options.
+
+extern crate getopts;
+extern crate metalcab;
+#[macro_use]
+extern crate serde_json;
+extern crate tiny_http;
+
+mod common;
+
+use common::{load_model, mecab_error, model_args, model_options, parse_number, tokens_json};
+use getopts::Options;
+use metalcab::compat::{MecabError, Model};
+use metalcab::pool::TaggerPool;
+use metalcab::snapshot::DictionaryInfoSnapshot;
+use serde_json::Value;
+use std::env;
+use std::ffi::CString;
+use std::io::Read;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+#[derive(Debug)]
+struct Config {
+    model_args: Vec<CString>,
+    address: String,
+    threads: usize,
+    max_nbest: usize,
+    max_body: usize,
+}
+
+/// An error response.
+#[derive(Debug)]
+struct HttpError {
+    status: u16,
+    message: String,
+}
+
+impl HttpError {
+    fn new<S: Into<String>>(status: u16, message: S) -> Self {
+        HttpError {
+            status,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<MecabError> for HttpError {
+    fn from(e: MecabError) -> Self {
+        HttpError::new(500, mecab_error(e))
+    }
+}
+
+fn options() -> Options {
+    let mut opts = Options::new();
+    model_options(&mut opts);
+    opts.optopt(
+        "a",
+        "address",
+        "listen on ADDR (default 127.0.0.1:8080)",
+        "ADDR",
+    );
+    opts.optopt(
+        "j",
+        "threads",
+        "handle N requests at once (default: the number of CPUs)",
+        "N",
+    );
+    opts.optopt(
+        "",
+        "max-nbest",
+        "reject /nbest requests for more than N paths (default 100)",
+        "N",
+    );
+    opts.optopt(
+        "",
+        "max-body",
+        "reject request bodies larger than N bytes (default 1048576)",
+        "N",
+    );
+    opts.optflag("h", "help", "show this help and exit");
+    opts
+}
+
+fn usage(opts: &Options) -> String {
+    opts.usage("Usage: metalcab-server [options]")
+}
+
+fn parse_args(args: &[String]) -> Result<Option<Config>, String> {
+    let opts = options();
+    let matches = opts.parse(args).map_err(|e| e.to_string())?;
+    if matches.opt_present("help") {
+        print!("{}", usage(&opts));
+        return Ok(None);
+    }
+    if !matches.free.is_empty() {
+        return Err(format!("unexpected argument: {}", matches.free[0]));
+    }
+
+    let model_args = model_args(&matches, "metalcab-server", &[])?;
+    let cpus = thread::available_parallelism().map_or(1, |n| n.get());
+    Ok(Some(Config {
+        model_args,
+        address: matches
+            .opt_str("address")
+            .unwrap_or_else(|| "127.0.0.1:8080".to_string()),
+        threads: parse_number(&matches, "threads", cpus)?,
+        max_nbest: parse_number(&matches, "max-nbest", 100)?,
+        max_body: parse_number(&matches, "max-body", 1 << 20)?,
+    }))
+}
+
+fn dictionary_json(model: &Model) -> Value {
+    let dictionaries = DictionaryInfoSnapshot::list(model.dictionary_info())
+        .into_iter()
+        .map(|info| {
+            json!({
+                "filename": info.filename,
+                "charset": info.charset,
+                "size": info.size,
+                "type": format!("{:?}", info.type_),
+                "lsize": info.lsize,
+                "rsize": info.rsize,
+                "version": info.version,
+            })
+        })
+        .collect();
+    json!({ "dictionaries": Value::Array(dictionaries) })
+}
+
+/// Reads the request body of at most `limit` bytes as a JSON object with a `text` string.
+fn read_body(request: &mut Request, limit: usize) -> Result<(Value, String), HttpError> {
+    let too_large = || HttpError::new(413, format!("the request body exceeds {} bytes", limit));
+    if request.body_length().is_some_and(|length| length > limit) {
+        return Err(too_large());
+    }
+    let mut body = Vec::new();
+    request
+        .as_reader()
+        .take(limit as u64 + 1)
+        .read_to_end(&mut body)
+        .map_err(|e| HttpError::new(400, format!("cannot read the request body: {}", e)))?;
+    if body.len() > limit {
+        return Err(too_large());
+    }
+    let body: Value = serde_json::from_slice(&body)
+        .map_err(|e| HttpError::new(400, format!("invalid JSON: {}", e)))?;
+    let text = match body.get("text") {
+        Some(Value::String(text)) => text.clone(),
+        _ => return Err(HttpError::new(400, "\"text\" must be a string")),
+    };
+    Ok((body, text))
+}
+
+fn handle(config: &Config, pool: &TaggerPool, request: &mut Request) -> Result<Value, HttpError> {
+    let path = request.url().split('?').next().unwrap_or("");
+    let method = request.method().clone();
+    match (path, method) {
+        ("/analyze", Method::Post) => {
+            let (_, text) = read_body(request, config.max_body)?;
+            let tokens = pool.get()?.tokenize(&text)?;
+            Ok(json!({ "text": text, "tokens": tokens_json(&tokens) }))
+        }
+        ("/nbest", Method::Post) => {
+            let (body, text) = read_body(request, config.max_body)?;
+            let n = match body.get("n") {
+                None => config.max_nbest.min(10),
+                Some(n) => match n.as_u64() {
+                    Some(n) if n > 0 && n as usize <= config.max_nbest => n as usize,
+                    _ => {
+                        return Err(HttpError::new(
+                            400,
+                            format!("\"n\" must be from 1 to {}", config.max_nbest),
+                        ))
+                    }
+                },
+            };
+            let paths = pool.get()?.tokenize_nbest(&text, n)?;
+            let nbest: Vec<Value> = paths.iter().map(|tokens| tokens_json(tokens)).collect();
+            Ok(json!({ "text": text, "nbest": nbest }))
+        }
+        ("/dictionary", Method::Get) => Ok(dictionary_json(&pool.model())),
+        ("/reload", Method::Post) => {
+            let model = Arc::new(load_model(&config.model_args)?);
+            pool.swap_model(model.clone());
+            Ok(dictionary_json(&model))
+        }
+        ("/analyze", _) | ("/nbest", _) | ("/reload", _) => {
+            Err(HttpError::new(405, "use POST for this endpoint"))
+        }
+        ("/dictionary", _) => Err(HttpError::new(405, "use GET for this endpoint")),
+        _ => Err(HttpError::new(404, format!("not found: {}", path))),
+    }
+}
+
+fn respond(config: &Config, pool: &TaggerPool, mut request: Request) {
+    let (status, body) = match handle(config, pool, &mut request) {
+        Ok(body) => (200, body),
+        Err(e) => (e.status, json!({ "error": e.message })),
+    };
+    let content_type = Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap();
+    let response = Response::from_string(body.to_string())
+        .with_status_code(status)
+        .with_header(content_type);
+    if let Err(e) = request.respond(response) {
+        eprintln!("metalcab-server: {}", e);
+    }
+}
+
+fn run(config: &Config) -> Result<(), String> {
+    let model = load_model(&config.model_args).map_err(mecab_error)?;
+    let pool = TaggerPool::new(Arc::new(model), config.threads);
+    let server = Server::http(&config.address).map_err(|e| format!("{}: {}", config.address, e))?;
+    eprintln!("metalcab-server: listening on {}", config.address);
+    let stopping = AtomicBool::new(false);
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..config.threads)
+            .map(|_| {
+                scope.spawn(|| loop {
+                    match server.recv() {
+                        Ok(request) => respond(config, &pool, request),
+                        // Unblocked by another worker which failed.
+                        Err(_) if stopping.swap(true, Ordering::SeqCst) => return Ok(()),
+                        Err(e) => {
+                            // Stop the other workers, so that the error is reported.
+                            for _ in 1..config.threads {
+                                server.unblock();
+                            }
+                            return Err(e.to_string());
+                        }
+                    }
+                })
+            })
+            .collect();
+        let results: Vec<_> = workers
+            .into_iter()
+            .map(|worker| worker.join().expect("worker panicked"))
+            .collect();
+        results.into_iter().collect()
+    })
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = parse_args(&args).and_then(|config| match config {
+        Some(config) => run(&config),
+        None => Ok(()),
+    });
+    if let Err(e) = result {
+        eprintln!("metalcab-server: {}", e);
+        process::exit(1);
+    }
+}
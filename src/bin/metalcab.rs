@@ -0,0 +1,377 @@
+//! `metalcab`: a command-line morphological analyzer
+//!
+//! Reads text from files or stdin, one sentence per line, and writes the analysis in MeCab's
+//! format, JSON, JSON Lines, CSV or CoNLL-U. Run with `--help` for the options.
+
+extern crate getopts;
+extern crate metalcab;
+#[macro_use]
+extern crate serde_json;
+
+mod common;
+
+use common::{load_model, mecab_error, model_args, model_options, parse_number, tokens_json};
+use getopts::Options;
+use metalcab::conllu::{ConlluExporter, UposMap};
+use metalcab::tokenizer::Tokenizer;
+use serde_json::Value;
+use std::env;
+use std::ffi::CString;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::process;
+use std::str::FromStr;
+use std::thread;
+
+/// The number of sentences read and analyzed at once.
+const BATCH_SIZE: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Mecab,
+    Json,
+    Jsonl,
+    Csv,
+    Conllu,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "mecab" => Ok(Format::Mecab),
+            "json" => Ok(Format::Json),
+            "jsonl" => Ok(Format::Jsonl),
+            "csv" => Ok(Format::Csv),
+            "conllu" => Ok(Format::Conllu),
+            _ => Err(format!("unknown format: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Config {
+    model_args: Vec<CString>,
+    format: Format,
+    nbest: usize,
+    partial: bool,
+    jobs: usize,
+    conllu: ConlluExporter,
+    inputs: Vec<String>,
+    output: Option<String>,
+}
+
+/// A sentence read from the input, with its 1-based number.
+struct Record {
+    id: usize,
+    text: String,
+}
+
+fn options() -> Options {
+    let mut opts = Options::new();
+    model_options(&mut opts);
+    opts.optopt(
+        "f",
+        "format",
+        "output format: mecab (default), json, jsonl, csv or conllu",
+        "FORMAT",
+    );
+    opts.optopt("N", "nbest", "output the N best results (default 1)", "N");
+    opts.optflag(
+        "p",
+        "partial",
+        "partial parsing mode; sentences end with EOS lines",
+    );
+    opts.optopt(
+        "",
+        "dictionary-type",
+        "features of the dictionary for CoNLL-U: ipadic (default) or unidic",
+        "TYPE",
+    );
+    opts.optopt(
+        "",
+        "upos",
+        "map parts of speech to UPOS with the table in FILE",
+        "FILE",
+    );
+    opts.optopt("j", "jobs", "analyze with N threads (default 1)", "N");
+    opts.optopt("o", "output", "write the result to FILE", "FILE");
+    opts.optflag("h", "help", "show this help and exit");
+    opts
+}
+
+fn usage(opts: &Options) -> String {
+    opts.usage("Usage: metalcab [options] [FILE...]")
+}
+
+fn parse_args(args: &[String]) -> Result<Option<Config>, String> {
+    let opts = options();
+    let matches = opts.parse(args).map_err(|e| e.to_string())?;
+    if matches.opt_present("help") {
+        print!("{}", usage(&opts));
+        return Ok(None);
+    }
+
+    let partial = matches.opt_present("partial");
+    let extra: &[&str] = if partial { &["-p"] } else { &[] };
+    let model_args = model_args(&matches, "metalcab", extra)?;
+
+    let mut conllu = match matches.opt_str("dictionary-type").as_deref() {
+        None | Some("ipadic") => ConlluExporter::ipadic(),
+        Some("unidic") => ConlluExporter::unidic(),
+        Some(other) => return Err(format!("unknown dictionary type: {}", other)),
+    };
+    if let Some(path) = matches.opt_str("upos") {
+        let table = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path, e))?;
+        *conllu.upos_mut() = UposMap::parse(&table).map_err(|e| format!("{}: {}", path, e))?;
+    }
+
+    Ok(Some(Config {
+        model_args,
+        format: matches
+            .opt_str("format")
+            .map_or(Ok(Format::Mecab), |s| s.parse())?,
+        nbest: parse_number(&matches, "nbest", 1)?,
+        partial,
+        jobs: parse_number(&matches, "jobs", 1)?,
+        conllu,
+        output: matches.opt_str("output"),
+        inputs: matches.free,
+    }))
+}
+
+/// Reads the next sentence: a line, or in partial mode, the lines up to `EOS`.
+fn read_record(reader: &mut dyn BufRead, partial: bool) -> io::Result<Option<String>> {
+    let mut record = String::new();
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(if partial && !record.is_empty() {
+                Some(record)
+            } else {
+                None
+            });
+        }
+        let trimmed = line.trim_end_matches(['\n', '\r']);
+        if !partial {
+            return Ok(Some(trimmed.to_string()));
+        }
+        if trimmed == "EOS" {
+            return Ok(Some(record));
+        }
+        record.push_str(trimmed);
+        record.push('\n');
+    }
+}
+
+fn quote_csv(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Analyzes a sentence and formats the result. For JSON, this is an object without the
+/// separator.
+fn analyze(tokenizer: &mut Tokenizer, config: &Config, record: &Record) -> Result<String, String> {
+    if config.format == Format::Mecab {
+        let tagger = tokenizer.tagger_mut();
+        let result = if config.nbest > 1 {
+            tagger.parseNBest(config.nbest, &record.text)
+        } else {
+            tagger.parse(&record.text)
+        };
+        return result.map(str::to_string).map_err(mecab_error);
+    }
+
+    let mut paths = if config.nbest > 1 {
+        tokenizer.tokenize_nbest(&record.text, config.nbest)
+    } else {
+        tokenizer.tokenize(&record.text).map(|tokens| vec![tokens])
+    }
+    .map_err(mecab_error)?;
+    let mut text = record.text.as_str();
+    let joined;
+    if config.partial {
+        // Surfaces do not point into the input, which contains constraints. Place the tokens
+        // in the concatenation of the surfaces.
+        for tokens in &mut paths {
+            let mut pos = 0;
+            for token in tokens {
+                token.start = pos;
+                pos += token.surface.len();
+                token.end = pos;
+            }
+        }
+        joined = paths[0]
+            .iter()
+            .map(|token| token.surface.as_str())
+            .collect::<String>();
+        text = &joined;
+    }
+
+    let mut out = String::new();
+    match config.format {
+        Format::Mecab => unreachable!(),
+        Format::Json | Format::Jsonl => {
+            let mut object = json!({ "id": record.id, "text": text });
+            if config.nbest > 1 {
+                object["nbest"] = Value::Array(paths.iter().map(|p| tokens_json(p)).collect());
+            } else {
+                object["tokens"] = tokens_json(&paths[0]);
+            }
+            out = object.to_string();
+        }
+        Format::Csv => {
+            for (rank, tokens) in paths.iter().enumerate() {
+                for (i, token) in tokens.iter().enumerate() {
+                    out.push_str(&format!(
+                        "{},{},{},{},{},{},{},{},{}\n",
+                        record.id,
+                        rank + 1,
+                        i + 1,
+                        quote_csv(&token.surface),
+                        token.start,
+                        token.end,
+                        token.posid,
+                        token.unknown,
+                        quote_csv(&token.feature),
+                    ));
+                }
+            }
+        }
+        Format::Conllu => {
+            for (rank, tokens) in paths.iter().enumerate() {
+                let id = if config.nbest > 1 {
+                    format!("{}-{}", record.id, rank + 1)
+                } else {
+                    record.id.to_string()
+                };
+                let mut buf = Vec::new();
+                config
+                    .conllu
+                    .write_tokens(&mut buf, Some(&id), text, tokens)
+                    .map_err(|e| e.to_string())?;
+                out.push_str(&String::from_utf8_lossy(&buf));
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Analyzes a batch with a tokenizer for each thread, returning the results in order.
+fn analyze_batch(
+    tokenizers: &mut [Tokenizer],
+    config: &Config,
+    batch: &[Record],
+) -> Result<Vec<String>, String> {
+    let chunk_size = batch.len().div_ceil(tokenizers.len()).max(1);
+    let results = thread::scope(|scope| {
+        let workers: Vec<_> = tokenizers
+            .iter_mut()
+            .zip(batch.chunks(chunk_size))
+            .map(|(tokenizer, chunk)| {
+                scope.spawn(move || {
+                    chunk
+                        .iter()
+                        .map(|record| analyze(tokenizer, config, record))
+                        .collect::<Result<Vec<_>, _>>()
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .map(|worker| worker.join().expect("worker panicked"))
+            .collect::<Vec<_>>()
+    });
+    let mut outputs = Vec::with_capacity(batch.len());
+    for result in results {
+        outputs.extend(result?);
+    }
+    Ok(outputs)
+}
+
+fn run(config: &Config) -> Result<(), String> {
+    let model = load_model(&config.model_args).map_err(mecab_error)?;
+    let mut tokenizers = (0..config.jobs)
+        .map(|_| model.createTagger().map(Tokenizer::new))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(mecab_error)?;
+
+    let mut readers: Vec<Box<dyn BufRead>> = Vec::new();
+    if config.inputs.is_empty() {
+        readers.push(Box::new(BufReader::new(io::stdin())));
+    }
+    for input in &config.inputs {
+        if input == "-" {
+            readers.push(Box::new(BufReader::new(io::stdin())));
+        } else {
+            let file = File::open(input).map_err(|e| format!("{}: {}", input, e))?;
+            readers.push(Box::new(BufReader::new(file)));
+        }
+    }
+    let out: Box<dyn Write> = match config.output {
+        Some(ref path) => Box::new(File::create(path).map_err(|e| format!("{}: {}", path, e))?),
+        None => Box::new(io::stdout()),
+    };
+    let mut out = BufWriter::new(out);
+    let io_error = |e: io::Error| e.to_string();
+
+    match config.format {
+        Format::Json => out.write_all(b"[").map_err(io_error)?,
+        Format::Csv => out
+            .write_all(b"sentence,rank,index,surface,start,end,posid,unknown,feature\n")
+            .map_err(io_error)?,
+        _ => {}
+    }
+    let mut id = 0;
+    let mut first = true;
+    for mut reader in readers {
+        loop {
+            let mut batch = Vec::with_capacity(BATCH_SIZE);
+            while batch.len() < BATCH_SIZE {
+                match read_record(&mut *reader, config.partial).map_err(io_error)? {
+                    Some(text) => {
+                        id += 1;
+                        batch.push(Record { id, text });
+                    }
+                    None => break,
+                }
+            }
+            if batch.is_empty() {
+                break;
+            }
+            for output in analyze_batch(&mut tokenizers, config, &batch)? {
+                match config.format {
+                    Format::Json => {
+                        let separator: &[u8] = if first { b"\n" } else { b",\n" };
+                        out.write_all(separator).map_err(io_error)?;
+                        out.write_all(output.as_bytes()).map_err(io_error)?;
+                    }
+                    Format::Jsonl => writeln!(out, "{}", output).map_err(io_error)?,
+                    _ => out.write_all(output.as_bytes()).map_err(io_error)?,
+                }
+                first = false;
+            }
+        }
+    }
+    if config.format == Format::Json {
+        out.write_all(b"\n]\n").map_err(io_error)?;
+    }
+    out.flush().map_err(io_error)
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = parse_args(&args).and_then(|config| match config {
+        Some(config) => run(&config),
+        None => Ok(()),
+    });
+    if let Err(e) = result {
+        eprintln!("metalcab: {}", e);
+        process::exit(1);
+    }
+}
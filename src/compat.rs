@@ -1,11 +1,16 @@
 use raw::*;
-use std::ffi::{CStr, NulError};
+use std::ffi::{CStr, NulError, OsStr};
 use std::marker::PhantomData;
 use std::ops::Drop;
 use std::os::raw::*;
+use std::path::{Path as FsPath, PathBuf};
 use std::ptr::{self, NonNull};
 use std::slice;
 use std::str::{self, Utf8Error};
+use std::sync::{Mutex, MutexGuard};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
 /// Model class
 #[derive(Debug)]
@@ -23,8 +28,9 @@ impl Model {
         let argc = args.len() as c_int;
         assert!(argc >= 0);
         assert_eq!(argc as usize, args.len());
-        let ptr = unsafe { mecab_model_new(argc, args.as_ptr() as *mut *mut c_char) };
-        let ptr = NonNull::new(ptr).ok_or_else(|| MecabError::last())?;
+        let ptr = MecabError::capture(|| unsafe {
+            mecab_model_new(argc, args.as_ptr() as *mut *mut c_char)
+        })?;
         Ok(Model(ptr))
     }
 
@@ -33,8 +39,8 @@ impl Model {
     /// Return NULL if new model cannot be initialized. Use MeCab::getLastError() to obtain the
     /// cause of the errors.
     pub fn create2(arg: &CStr) -> Result<Self, MecabError> {
-        let ptr = unsafe { mecab_model_new2(arg.as_ptr() as *const c_char) };
-        let ptr = NonNull::new(ptr).ok_or_else(|| MecabError::last())?;
+        let ptr =
+            MecabError::capture(|| unsafe { mecab_model_new2(arg.as_ptr() as *const c_char) })?;
         Ok(Model(ptr))
     }
 
@@ -43,17 +49,15 @@ impl Model {
     /// Never delete this model object before deleting tagger object.
     #[allow(non_snake_case)]
     pub fn createTagger(&self) -> Result<Tagger, MecabError> {
-        let tagger = unsafe { Tagger::from_ptr(mecab_model_new_tagger(self.as_ptr())) };
-        let tagger = tagger.ok_or_else(|| MecabError::last())?;
-        Ok(tagger)
+        let ptr = MecabError::capture(|| unsafe { mecab_model_new_tagger(self.as_ptr()) })?;
+        Ok(Tagger(ptr, PhantomData))
     }
 
     /// Create a new Lattice object.
     #[allow(non_snake_case)]
     pub fn createLattice(&self) -> Result<Lattice, MecabError> {
-        let lattice = unsafe { Lattice::from_ptr(mecab_model_new_lattice(self.as_ptr())) };
-        let lattice = lattice.ok_or_else(|| MecabError::last())?;
-        Ok(lattice)
+        let ptr = MecabError::capture(|| unsafe { mecab_model_new_lattice(self.as_ptr()) })?;
+        Ok(Lattice(ptr))
     }
 
     /// Swap the instance with `model`.
@@ -77,6 +81,7 @@ impl Model {
     /// Moreover, `Model::swap` itself has a race condition, meaning that you cannot
     /// call `Model::swap` on the same model in a racy way.
     pub unsafe fn swap(&self, new_model: &Model) -> Result<(), MecabError> {
+        let _guard = MecabError::lock_last();
         let result = mecab_model_swap(self.as_ptr(), new_model.as_ptr());
         if result != 0 {
             Ok(())
@@ -143,14 +148,13 @@ impl Tagger<'static> {
         let argc = args.len() as c_int;
         assert!(argc >= 0);
         assert_eq!(argc as usize, args.len());
-        let ptr = unsafe { mecab_new(argc, args.as_ptr() as *mut *mut c_char) };
-        let ptr = NonNull::new(ptr).ok_or_else(|| MecabError::last())?;
+        let ptr =
+            MecabError::capture(|| unsafe { mecab_new(argc, args.as_ptr() as *mut *mut c_char) })?;
         Ok(Tagger(ptr, PhantomData))
     }
 
     pub fn create2(arg: &CStr) -> Result<Self, MecabError> {
-        let ptr = unsafe { mecab_new2(arg.as_ptr() as *const c_char) };
-        let ptr = NonNull::new(ptr).ok_or_else(|| MecabError::last())?;
+        let ptr = MecabError::capture(|| unsafe { mecab_new2(arg.as_ptr() as *const c_char) })?;
         Ok(Tagger(ptr, PhantomData))
     }
 }
@@ -210,6 +214,38 @@ impl<'model> Tagger<'model> {
         }
     }
 
+    /// Parses `s` and writes the result into `buf`, replacing its contents.
+    ///
+    /// Unlike [`parse`](#method.parse), the result outlives the next call on this tagger.
+    /// `buf` is grown as needed and can be reused across calls to avoid allocations.
+    pub fn parse_into<B: OutputBuffer>(&mut self, s: &str, buf: &mut B) -> Result<(), MecabError> {
+        buf.fill_with(|buf| {
+            fill_output_buffer(
+                buf,
+                |ostr, olen| unsafe {
+                    mecab_sparse_tostr3(
+                        self.as_ptr(),
+                        s.as_ptr() as *const c_char,
+                        s.len(),
+                        ostr,
+                        olen,
+                    )
+                },
+                || self.last_error(),
+            )
+        })
+    }
+
+    /// Parses the sentence set to `lattice`, storing the result in it.
+    pub fn parse_lattice(&self, lattice: &mut Lattice) -> Result<(), MecabError> {
+        let result = unsafe { mecab_parse_lattice(self.as_ptr(), lattice.as_ptr()) };
+        if result != 0 {
+            Ok(())
+        } else {
+            Err(lattice.last_error())
+        }
+    }
+
     #[allow(non_snake_case)]
     pub fn parseToNode_cstr(&mut self, s: &CStr) -> Result<&Node, MecabError> {
         let ptr = unsafe { mecab_sparse_tonode(self.as_ptr(), s.as_ptr()) };
@@ -242,6 +278,34 @@ impl<'model> Tagger<'model> {
         }
     }
 
+    /// Parses `s` and writes the `n` best results into `buf`, replacing its contents.
+    ///
+    /// See [`parse_into`](#method.parse_into) for the buffer handling.
+    #[allow(non_snake_case)]
+    pub fn parseNBest_into<B: OutputBuffer>(
+        &mut self,
+        n: usize,
+        s: &str,
+        buf: &mut B,
+    ) -> Result<(), MecabError> {
+        buf.fill_with(|buf| {
+            fill_output_buffer(
+                buf,
+                |ostr, olen| unsafe {
+                    mecab_nbest_sparse_tostr3(
+                        self.as_ptr(),
+                        n,
+                        s.as_ptr() as *const c_char,
+                        s.len(),
+                        ostr,
+                        olen,
+                    )
+                },
+                || self.last_error(),
+            )
+        })
+    }
+
     #[allow(non_snake_case)]
     pub fn parseNBestInit_cstr(&mut self, s: &CStr) -> Result<(), MecabError> {
         let result = unsafe { mecab_nbest_init(self.as_ptr(), s.as_ptr()) };
@@ -267,6 +331,17 @@ impl<'model> Tagger<'model> {
         unsafe { self.convert_result_str(mecab_nbest_next_tostr(self.as_ptr())) }
     }
 
+    /// Writes the next result into `buf`, replacing its contents.
+    ///
+    /// `mecab_nbest_next_tostr2` consumes the result even if the buffer overflows, so the
+    /// result is copied from the tagger's internal buffer instead.
+    pub fn next_into<B: OutputBuffer>(&mut self, buf: &mut B) -> Result<(), MecabError> {
+        buf.fill_with(|buf| {
+            let ptr = unsafe { mecab_nbest_next_tostr(self.as_ptr()) };
+            unsafe { copy_output_buffer(buf, ptr) }.ok_or_else(|| self.last_error())
+        })
+    }
+
     #[allow(non_snake_case)]
     pub fn nextNode(&mut self) -> Result<&Node, MecabError> {
         let ptr = unsafe { mecab_nbest_next_tonode(self.as_ptr()) };
@@ -336,6 +411,82 @@ impl Lattice {
         (unsafe { mecab_lattice_is_available(self.as_ptr()) }) != 0
     }
 
+    /// Sets the sentence to be parsed by [`Tagger::parse_lattice`].
+    ///
+    /// The sentence is copied into the lattice, adding `MECAB_ALLOCATE_SENTENCE` to the
+    /// request type.
+    ///
+    /// [`Tagger::parse_lattice`]: struct.Tagger.html#method.parse_lattice
+    pub fn set_sentence(&mut self, sentence: &str) {
+        unsafe {
+            mecab_lattice_add_request_type(self.as_ptr(), MECAB_ALLOCATE_SENTENCE);
+            mecab_lattice_set_sentence2(
+                self.as_ptr(),
+                sentence.as_ptr() as *const c_char,
+                sentence.len(),
+            );
+        }
+    }
+
+    /// Return the sentence set to the lattice, or `None` if it is unset or not valid UTF-8.
+    pub fn sentence(&self) -> Option<&str> {
+        let ptr = unsafe { mecab_lattice_get_sentence(self.as_ptr()) };
+        if ptr.is_null() {
+            return None;
+        }
+        let bytes = unsafe { slice::from_raw_parts(ptr as *const u8, self.size()) };
+        str::from_utf8(bytes).ok()
+    }
+
+    /// Return the request type, a combination of `MECAB_ONE_BEST`, `MECAB_NBEST`, and so on.
+    pub fn request_type(&self) -> i32 {
+        (unsafe { mecab_lattice_get_request_type(self.as_ptr()) }) as i32
+    }
+
+    pub fn has_request_type(&self, request_type: i32) -> bool {
+        (unsafe { mecab_lattice_has_request_type(self.as_ptr(), request_type as c_int) }) != 0
+    }
+
+    pub fn set_request_type(&mut self, request_type: i32) {
+        unsafe { mecab_lattice_set_request_type(self.as_ptr(), request_type as c_int) };
+    }
+
+    pub fn add_request_type(&mut self, request_type: i32) {
+        unsafe { mecab_lattice_add_request_type(self.as_ptr(), request_type as c_int) };
+    }
+
+    pub fn remove_request_type(&mut self, request_type: i32) {
+        unsafe { mecab_lattice_remove_request_type(self.as_ptr(), request_type as c_int) };
+    }
+
+    pub fn has_constraint(&self) -> bool {
+        (unsafe { mecab_lattice_has_constraint(self.as_ptr()) }) != 0
+    }
+
+    /// Return the boundary constraint at the byte position `pos`.
+    pub fn boundary_constraint(&self, pos: usize) -> BoundaryConstraintType {
+        let size = self.size();
+        assert!(pos <= size, "index out of range");
+        let type_ = unsafe { mecab_lattice_get_boundary_constraint(self.as_ptr(), pos) };
+        if type_ == MECAB_ANY_BOUNDARY {
+            BoundaryConstraintType::MECAB_ANY_BOUNDARY
+        } else if type_ == MECAB_TOKEN_BOUNDARY {
+            BoundaryConstraintType::MECAB_TOKEN_BOUNDARY
+        } else if type_ == MECAB_INSIDE_TOKEN {
+            BoundaryConstraintType::MECAB_INSIDE_TOKEN
+        } else {
+            panic!("Unknown BoundaryConstraintType: {}", type_)
+        }
+    }
+
+    /// Sets the boundary constraint at the byte position `pos` of the sentence.
+    /// Call this after [`set_sentence`](#method.set_sentence).
+    pub fn set_boundary_constraint(&mut self, pos: usize, type_: BoundaryConstraintType) {
+        let size = self.size();
+        assert!(pos <= size, "index out of range");
+        unsafe { mecab_lattice_set_boundary_constraint(self.as_ptr(), pos, type_ as c_int) };
+    }
+
     pub fn bos_node(&self) -> Option<&Node> {
         unsafe { Node::from_ptr(mecab_lattice_get_bos_node(self.as_ptr())) }
     }
@@ -376,6 +527,47 @@ impl Lattice {
         unsafe { mecab_lattice_get_size(self.as_ptr()) }
     }
 
+    /// Moves the best path to the next one, for a lattice parsed with `MECAB_NBEST`.
+    /// Return false if there are no more paths.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> bool {
+        (unsafe { mecab_lattice_next(self.as_ptr()) }) != 0
+    }
+
+    /// Writes the result into `buf`, replacing its contents.
+    ///
+    /// `buf` is grown as needed and can be reused across calls to avoid allocations.
+    #[allow(non_snake_case)]
+    pub fn toString_into<B: OutputBuffer>(&self, buf: &mut B) -> Result<(), MecabError> {
+        buf.fill_with(|buf| {
+            fill_output_buffer(
+                buf,
+                |ostr, olen| unsafe { mecab_lattice_tostr2(self.as_ptr(), ostr, olen) },
+                || self.last_error(),
+            )
+        })
+    }
+
+    /// Writes the `n` best results into `buf`, replacing its contents.
+    ///
+    /// `mecab_lattice_nbest_tostr2` consumes the results even if the buffer overflows, so
+    /// they are copied from the lattice's internal buffer instead.
+    #[allow(non_snake_case)]
+    pub fn enumNBestAsString_into<B: OutputBuffer>(
+        &self,
+        n: usize,
+        buf: &mut B,
+    ) -> Result<(), MecabError> {
+        buf.fill_with(|buf| {
+            let ptr = unsafe { mecab_lattice_nbest_tostr(self.as_ptr(), n) };
+            unsafe { copy_output_buffer(buf, ptr) }.ok_or_else(|| self.last_error())
+        })
+    }
+
+    pub fn last_error(&self) -> MecabError {
+        unsafe { MecabError::from_lattice(self.as_ptr()) }
+    }
+
     pub fn as_ptr(&self) -> *mut mecab_lattice_t {
         self.0.as_ptr()
     }
@@ -398,6 +590,80 @@ impl Drop for Lattice {
 unsafe impl Send for Lattice {}
 unsafe impl Sync for Lattice {}
 
+/// A reusable buffer which formatted results are written into.
+///
+/// It is implemented for `Vec<u8>` and `String`; the latter additionally checks that the
+/// result is valid UTF-8.
+pub trait OutputBuffer {
+    /// Calls `f` with the underlying bytes. `f` leaves them either empty or filled with a
+    /// complete result.
+    fn fill_with<F>(&mut self, f: F) -> Result<(), MecabError>
+    where
+        F: FnOnce(&mut Vec<u8>) -> Result<(), MecabError>;
+}
+
+impl OutputBuffer for Vec<u8> {
+    fn fill_with<F>(&mut self, f: F) -> Result<(), MecabError>
+    where
+        F: FnOnce(&mut Vec<u8>) -> Result<(), MecabError>,
+    {
+        f(self)
+    }
+}
+
+impl OutputBuffer for String {
+    fn fill_with<F>(&mut self, f: F) -> Result<(), MecabError>
+    where
+        F: FnOnce(&mut Vec<u8>) -> Result<(), MecabError>,
+    {
+        let bytes = unsafe { self.as_mut_vec() };
+        let result = f(bytes);
+        if let Err(e) = str::from_utf8(bytes) {
+            bytes.clear();
+            return Err(e.into());
+        }
+        result
+    }
+}
+
+/// The initial capacity given to an empty output buffer.
+const INITIAL_OUTPUT_BUFFER_SIZE: usize = 8192;
+
+/// Calls a formatting function taking `(ostr, olen)` until the output fits in `buf`,
+/// doubling its capacity on `OutputBufferOverflow`.
+fn fill_output_buffer<F, E>(buf: &mut Vec<u8>, mut f: F, last_error: E) -> Result<(), MecabError>
+where
+    F: FnMut(*mut c_char, usize) -> *const c_char,
+    E: Fn() -> MecabError,
+{
+    buf.clear();
+    buf.reserve(INITIAL_OUTPUT_BUFFER_SIZE);
+    loop {
+        let capacity = buf.capacity();
+        let ptr = f(buf.as_mut_ptr() as *mut c_char, capacity);
+        if !ptr.is_null() {
+            let len = unsafe { CStr::from_ptr(ptr) }.to_bytes().len();
+            unsafe { buf.set_len(len) };
+            return Ok(());
+        }
+        match last_error() {
+            MecabError::OutputBufferOverflow => buf.reserve(capacity * 2),
+            e => return Err(e),
+        }
+    }
+}
+
+/// Replaces the contents of `buf` with a NUL-terminated string, or returns `None` if `ptr`
+/// is NULL.
+unsafe fn copy_output_buffer(buf: &mut Vec<u8>, ptr: *const c_char) -> Option<()> {
+    buf.clear();
+    if ptr.is_null() {
+        return None;
+    }
+    buf.extend_from_slice(CStr::from_ptr(ptr).to_bytes());
+    Some(())
+}
+
 pub struct DictionaryInfo<'a>(mecab_dictionary_info_t, PhantomData<&'a ()>);
 
 impl<'a> DictionaryInfo<'a> {
@@ -468,6 +734,7 @@ unsafe impl<'a> Sync for DictionaryInfo<'a> {}
 
 #[allow(non_camel_case_types)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum DictionaryType {
     /// This is a system dictionary.
     MECAB_SYS_DIC = 0,
@@ -479,6 +746,19 @@ pub enum DictionaryType {
     MECAB_UNK_DIC = 2,
 }
 
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum BoundaryConstraintType {
+    /// The token boundary is not specified.
+    MECAB_ANY_BOUNDARY = 0,
+
+    /// The position is a strong token boundary.
+    MECAB_TOKEN_BOUNDARY = 1,
+
+    /// The position is not a token boundary.
+    MECAB_INSIDE_TOKEN = 2,
+}
+
 pub struct Path<'a>(mecab_path_t, PhantomData<&'a ()>);
 
 impl<'a> Path<'a> {
@@ -647,6 +927,9 @@ impl<'a> Node<'a> {
 unsafe impl<'a> Send for Node<'a> {}
 unsafe impl<'a> Sync for Node<'a> {}
 
+/// Serializes the calls which set MeCab's process-global error message.
+static LAST_ERROR_LOCK: Mutex<()> = Mutex::new(());
+
 #[derive(Debug)]
 pub enum MecabError {
     NulString(NulError),
@@ -662,6 +945,23 @@ pub enum MecabError {
     OutputBufferOverflow,
     NoAtomicSwap,
     UnknownError,
+    /// The dictionary directory given by `-d` or `dicdir` does not exist, or has no `dicrc`.
+    DictionaryDirNotFound(PathBuf),
+    /// A resource file, such as `mecabrc`, `dicrc`, `sys.dic` or `matrix.bin`, cannot be opened.
+    FileNotFound(PathBuf),
+    /// A compiled dictionary file is truncated or corrupted.
+    BrokenDictionary(PathBuf),
+    /// The dictionary directory does not contain a system dictionary.
+    NotSystemDictionary(PathBuf),
+    /// The file passed as a user dictionary is not a user dictionary.
+    NotUserDictionary(PathBuf),
+    /// A user dictionary does not match the system dictionary in charset, version, or
+    /// the number of context IDs.
+    IncompatibleDictionary(PathBuf),
+    /// A compiled dictionary file has this format version, which MeCab doesn't support.
+    IncompatibleVersion(u32),
+    /// An option is unrecognized or has a missing or superfluous argument.
+    InvalidOption(String),
     Other(String),
     OtherBytes(Vec<u8>),
 }
@@ -676,9 +976,93 @@ impl MecabError {
         Self::from(bytes)
     }
 
+    unsafe fn from_lattice(ptr: *mut mecab_lattice_t) -> Self {
+        let eptr = mecab_lattice_strerror(ptr);
+        if eptr.is_null() {
+            return MecabError::UnknownError;
+        }
+        let bytes = CStr::from_ptr(eptr).to_bytes();
+        Self::from(bytes)
+    }
+
+    /// Reads MeCab's process-global error message.
+    ///
+    /// The caller must hold the lock returned by [`lock_last`](#method.lock_last) since the
+    /// failing call; otherwise another thread may overwrite the message in between.
     fn last() -> Self {
         unsafe { Self::from_tagger(ptr::null_mut()) }
     }
+
+    /// Locks MeCab's process-global error message.
+    fn lock_last() -> MutexGuard<'static, ()> {
+        LAST_ERROR_LOCK
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Calls a constructor which reports failures through the process-global error message,
+    /// and captures the message before any other thread can overwrite it.
+    fn capture<T, F>(f: F) -> Result<NonNull<T>, Self>
+    where
+        F: FnOnce() -> *mut T,
+    {
+        let _guard = Self::lock_last();
+        NonNull::new(f()).ok_or_else(Self::last)
+    }
+
+    /// Classifies an error reported while creating a model or a tagger.
+    ///
+    /// Such messages are prefixed with the failed checks, e.g.
+    /// `tagger.cpp(151) [load_dictionary_resource(param)] param.cpp(71) [ifs] no such file or
+    /// directory: /usr/local/lib/mecab/dic/ipadic/dicrc`, so only the innermost message is
+    /// inspected. The classification depends on the message only, not on the file system.
+    fn from_diagnostic(s: &str) -> Option<Self> {
+        use self::MecabError::*;
+        fn after<'a>(s: &'a str, needle: &str) -> Option<&'a str> {
+            s.rfind(needle).map(|pos| &s[pos + needle.len()..])
+        }
+
+        if let Some(path) =
+            after(s, "no such file or directory: ").or_else(|| after(s, "open failed: "))
+        {
+            let path = FsPath::new(path);
+            // `dicrc` is the first file read from the dictionary directory.
+            if path.file_name() == Some(OsStr::new("dicrc")) {
+                if let Some(dicdir) = path.parent() {
+                    return Some(DictionaryDirNotFound(dicdir.to_path_buf()));
+                }
+            }
+            return Some(FileNotFound(path.to_path_buf()));
+        }
+        if let Some(path) = after(s, "dictionary file is broken: ") {
+            return Some(BrokenDictionary(PathBuf::from(path)));
+        }
+        if let Some(path) = after(s, "not a system dictionary: ") {
+            return Some(NotSystemDictionary(PathBuf::from(path)));
+        }
+        if let Some(path) = after(s, "not a user dictionary: ") {
+            return Some(NotUserDictionary(PathBuf::from(path)));
+        }
+        if let Some(path) = after(s, "incompatible dictionary: ") {
+            return Some(IncompatibleDictionary(PathBuf::from(path)));
+        }
+        if let Some(version) = after(s, "incompatible version: ") {
+            if let Ok(version) = version.trim().parse() {
+                return Some(IncompatibleVersion(version));
+            }
+        }
+        if let Some(option) = after(s, "unrecognized option `") {
+            return Some(InvalidOption(option.trim_end_matches('`').to_string()));
+        }
+        for suffix in &["` requires an argument", "` doesn't allow an argument"] {
+            if let Some(s) = s.strip_suffix(suffix) {
+                if let Some(pos) = s.rfind('`') {
+                    return Some(InvalidOption(s[pos + 1..].to_string()));
+                }
+            }
+        }
+        None
+    }
 }
 
 impl From<NulError> for MecabError {
@@ -700,7 +1084,7 @@ impl<'a> From<&'a str> for MecabError {
             "Model is not available" => return ModelNotAvailable,
             "current model is not available" => return CurrentModelNotAvailable,
             "Passed model is not available" => return PassedModelNotAvailable,
-            "Invalid model is passed" => return ModelNotAvailable,
+            "Invalid model is passed" => return InvalidModel,
             "no more results" => return NoMoreResults,
             "MECAB_NBEST request type is not set" => return NBestRequired,
             "nbest size must be 1 <= nbest <= 512" => return NBestSizeOutOfRange,
@@ -710,7 +1094,7 @@ impl<'a> From<&'a str> for MecabError {
             "Unknown Error" => return UnknownError,
             _ => {}
         }
-        MecabError::Other(s.to_string())
+        Self::from_diagnostic(s).unwrap_or_else(|| MecabError::Other(s.to_string()))
     }
 }
 
@@ -723,3 +1107,106 @@ impl<'a> From<&'a [u8]> for MecabError {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CString;
+    use std::thread;
+
+    /// Races failing constructions against successful ones and checks that each error is
+    /// the one caused by its own call. Requires the default dictionary.
+    #[test]
+    fn test_errors_are_attributed_to_their_calls() {
+        let threads: Vec<_> = (0..8)
+            .map(|i| {
+                thread::spawn(move || {
+                    let empty = CString::new("").unwrap();
+                    for j in 0..50 {
+                        let dicdir = format!("/nonexistent/metalcab-{}-{}", i, j);
+                        let expected = PathBuf::from(&dicdir);
+                        let arg = CString::new(format!("-d {}", dicdir)).unwrap();
+                        let args = [
+                            CString::new("mecab").unwrap(),
+                            CString::new("-d").unwrap(),
+                            CString::new(dicdir.clone()).unwrap(),
+                        ];
+                        let args: Vec<&CStr> = args.iter().map(|arg| arg.as_c_str()).collect();
+                        match (i + j) % 4 {
+                            0 => match Model::create(&args) {
+                                Err(MecabError::DictionaryDirNotFound(path)) => {
+                                    assert_eq!(path, expected)
+                                }
+                                other => panic!("{}: unexpected result {:?}", dicdir, other),
+                            },
+                            1 => match Tagger::create2(&arg) {
+                                Err(MecabError::DictionaryDirNotFound(path)) => {
+                                    assert_eq!(path, expected)
+                                }
+                                other => panic!("{}: unexpected result {:?}", dicdir, other),
+                            },
+                            2 => {
+                                let model = Model::create2(&empty).unwrap();
+                                Tagger::create2(&empty).unwrap();
+                                model.createTagger().unwrap();
+                            }
+                            _ => {
+                                let option = format!("--metalcab-{}-{}", i, j);
+                                let arg = CString::new(option.clone()).unwrap();
+                                match Model::create2(&arg) {
+                                    Err(MecabError::InvalidOption(name)) => {
+                                        assert_eq!(name, option)
+                                    }
+                                    other => panic!("{}: unexpected result {:?}", option, other),
+                                }
+                            }
+                        }
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_classify_diagnostics() {
+        let cases = [
+            (
+                "tagger.cpp(151) [load_dictionary_resource(param)] param.cpp(71) [ifs] no such \
+                 file or directory: /nonexistent/ipadic/dicrc",
+                "DictionaryDirNotFound(\"/nonexistent/ipadic\")",
+            ),
+            (
+                "tagger.cpp(157) [model->open(param)] tokenizer.cpp(127) \
+                 [dictionary_info_[0]->open(sysdic.c_str(), \"r\")] mmap.h(153) [(fd = \
+                 ::open(filename, flag | O_BINARY)) >= 0] open failed: /dic/sys.dic",
+                "FileNotFound(\"/dic/sys.dic\")",
+            ),
+            (
+                "dictionary.cpp(376) [dmmap_->size() >= 100] dictionary file is broken: \
+                 /dic/sys.dic",
+                "BrokenDictionary(\"/dic/sys.dic\")",
+            ),
+            (
+                "dictionary.cpp(388) [version_ == DIC_VERSION] incompatible version: 101",
+                "IncompatibleVersion(101)",
+            ),
+            (
+                "tokenizer.cpp(131) [dictionary_info_[i]->type == MECAB_USR_DIC] not a user \
+                 dictionary: /dic/user.dic",
+                "NotUserDictionary(\"/dic/user.dic\")",
+            ),
+            ("unrecognized option `--foo`", "InvalidOption(\"--foo\")"),
+            (
+                "`--dicdir` requires an argument",
+                "InvalidOption(\"--dicdir\")",
+            ),
+            ("something else", "Other(\"something else\")"),
+        ];
+        for &(message, expected) in &cases {
+            assert_eq!(format!("{:?}", MecabError::from(message)), expected);
+        }
+    }
+}
@@ -0,0 +1,345 @@
+//! CoNLL-U export
+//!
+//! [`ConlluExporter`](struct.ConlluExporter.html) writes analyses in the
+//! [CoNLL-U](https://universaldependencies.org/format.html) format, filling ID, FORM, LEMMA,
+//! UPOS, XPOS, FEATS and MISC. HEAD, DEPREL and DEPS are left empty for a dependency parser.
+//!
+//! UPOS is mapped from the MeCab part of speech with a [`UposMap`](struct.UposMap.html).
+//! Tables for IPADIC and UniDic are shipped with the crate, in `data/upos-ipadic.tsv` and
+//! `data/upos-unidic.tsv`.
+
+use compat::Node;
+use std::error;
+use std::fmt;
+use std::io::{self, Write};
+use tokenizer::{pos_matches, split_features, Token};
+
+const IPADIC_TABLE: &str = include_str!("../data/upos-ipadic.tsv");
+const UNIDIC_TABLE: &str = include_str!("../data/upos-unidic.tsv");
+
+/// A table mapping MeCab parts of speech to Universal Dependencies UPOS tags.
+///
+/// Each rule is a prefix of the POS fields, where `*` matches any field. The rule with the
+/// longest matching prefix wins, and `X` is used if none matches.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UposMap {
+    rules: Vec<(Vec<String>, String)>,
+}
+
+impl UposMap {
+    /// Creates an empty table, which maps everything to `X`.
+    pub fn new() -> Self {
+        UposMap { rules: Vec::new() }
+    }
+
+    /// The table for IPADIC.
+    pub fn ipadic() -> Self {
+        UposMap::parse(IPADIC_TABLE).unwrap()
+    }
+
+    /// The table for UniDic.
+    pub fn unidic() -> Self {
+        UposMap::parse(UNIDIC_TABLE).unwrap()
+    }
+
+    /// Parses a table. Each line is a comma-separated prefix of the POS fields and a UPOS
+    /// tag, separated by a tab, e.g., `名詞,固有名詞\tPROPN`. Empty lines and lines
+    /// starting with `#` are ignored.
+    pub fn parse(table: &str) -> Result<Self, UposTableError> {
+        let mut map = UposMap::new();
+        for (i, line) in table.lines().enumerate() {
+            let line = line.trim_end_matches('\r');
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut columns = line.split('\t');
+            match (columns.next(), columns.next(), columns.next()) {
+                (Some(pos), Some(upos), None) if !upos.is_empty() => {
+                    map.insert(&split_features(pos), upos);
+                }
+                _ => {
+                    return Err(UposTableError {
+                        line: i + 1,
+                        content: line.to_string(),
+                    })
+                }
+            }
+        }
+        Ok(map)
+    }
+
+    /// Adds a rule, replacing the one with the same prefix.
+    pub fn insert(&mut self, pos: &[&str], upos: &str) {
+        let pos: Vec<String> = pos.iter().map(|f| f.to_string()).collect();
+        match self.rules.iter_mut().find(|rule| rule.0 == pos) {
+            Some(rule) => rule.1 = upos.to_string(),
+            None => self.rules.push((pos, upos.to_string())),
+        }
+    }
+
+    /// Return the UPOS tag for the feature fields.
+    pub fn get(&self, features: &[&str]) -> &str {
+        self.rules
+            .iter()
+            .filter(|&(pos, _)| pos_matches(pos, features))
+            .max_by_key(|&(pos, _)| pos.len())
+            .map_or("X", |(_, upos)| upos.as_str())
+    }
+}
+
+impl Default for UposMap {
+    fn default() -> Self {
+        UposMap::new()
+    }
+}
+
+/// Error from [`UposMap::parse`](struct.UposMap.html#method.parse).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UposTableError {
+    /// The 1-based line number.
+    pub line: usize,
+    pub content: String,
+}
+
+impl fmt::Display for UposTableError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "malformed UPOS rule at line {}: {:?}",
+            self.line, self.content
+        )
+    }
+}
+
+impl error::Error for UposTableError {}
+
+/// Writes sentences in the CoNLL-U format.
+///
+/// - LEMMA is the lemma field of the features, or FORM if it is missing or `*`.
+/// - XPOS is the POS fields other than `*`, joined with `-`, e.g., `名詞-固有名詞-人名-名`.
+/// - FEATS is `_`, since MeCab dictionaries have no equivalent of UD features.
+/// - MISC has `SpaceAfter=No` where the next token follows immediately, and
+///   `ByteRange=start:end`, the byte offsets of the token in the input.
+///
+/// ```rust,no_run
+/// use metalcab::compat::Tagger;
+/// use metalcab::conllu::ConlluExporter;
+/// use std::ffi::CStr;
+/// use std::io;
+///
+/// let mut tagger = Tagger::create2(CStr::from_bytes_with_nul(b"\0").unwrap()).unwrap();
+/// let text = "太郎は花子に本を渡した。";
+/// let node = tagger.parseToNode(text).unwrap();
+/// ConlluExporter::ipadic()
+///     .write_nodes(&mut io::stdout(), Some("1"), text, node)
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConlluExporter {
+    upos: UposMap,
+    pos_fields: usize,
+    lemma_field: usize,
+}
+
+impl ConlluExporter {
+    /// Creates an exporter with a UPOS table, the number of POS fields and the index of the
+    /// lemma field in the features.
+    pub fn new(upos: UposMap, pos_fields: usize, lemma_field: usize) -> Self {
+        ConlluExporter {
+            upos,
+            pos_fields,
+            lemma_field,
+        }
+    }
+
+    /// Creates an exporter for IPADIC, whose features are
+    /// `品詞,品詞細分類1,品詞細分類2,品詞細分類3,活用型,活用形,原形,読み,発音`.
+    pub fn ipadic() -> Self {
+        ConlluExporter::new(UposMap::ipadic(), 4, 6)
+    }
+
+    /// Creates an exporter for UniDic, whose features are
+    /// `pos1,pos2,pos3,pos4,cType,cForm,lForm,lemma,...`.
+    pub fn unidic() -> Self {
+        ConlluExporter::new(UposMap::unidic(), 4, 7)
+    }
+
+    pub fn upos(&self) -> &UposMap {
+        &self.upos
+    }
+
+    pub fn upos_mut(&mut self) -> &mut UposMap {
+        &mut self.upos
+    }
+
+    /// Writes a sentence from the result of
+    /// [`Tagger::parseToNode`][compat::Tagger::parseToNode] for `text`.
+    ///
+    /// [compat::Tagger::parseToNode]: ../compat/struct.Tagger.html#method.parseToNode
+    pub fn write_nodes<W: Write>(
+        &self,
+        out: &mut W,
+        sent_id: Option<&str>,
+        text: &str,
+        node: &Node,
+    ) -> io::Result<()> {
+        let mut tokens = Vec::new();
+        let mut node = Some(node);
+        while let Some(n) = node {
+            tokens.extend(Token::from_node(n, text, 0));
+            node = n.next();
+        }
+        self.write_tokens(out, sent_id, text, &tokens)
+    }
+
+    /// Writes a sentence of tokens whose offsets are relative to `text`.
+    pub fn write_tokens<W: Write>(
+        &self,
+        out: &mut W,
+        sent_id: Option<&str>,
+        text: &str,
+        tokens: &[Token],
+    ) -> io::Result<()> {
+        if let Some(sent_id) = sent_id {
+            writeln!(out, "# sent_id = {}", sent_id)?;
+        }
+        writeln!(out, "# text = {}", text.replace(['\n', '\r'], " "))?;
+        for (i, token) in tokens.iter().enumerate() {
+            let features = split_features(&token.feature);
+            let pos = &features[..self.pos_fields.min(features.len())];
+            let lemma = match features.get(self.lemma_field) {
+                Some(&lemma) if !lemma.is_empty() && lemma != "*" => lemma,
+                _ => &token.surface,
+            };
+            let xpos: Vec<&str> = pos.iter().cloned().filter(|&f| f != "*").collect();
+            let mut misc = Vec::new();
+            let next_start = tokens.get(i + 1).map(|next| next.start);
+            if next_start == Some(token.end) {
+                misc.push("SpaceAfter=No".to_string());
+            }
+            misc.push(format!("ByteRange={}:{}", token.start, token.end));
+            writeln!(
+                out,
+                "{}\t{}\t{}\t{}\t{}\t_\t_\t_\t_\t{}",
+                i + 1,
+                field(&token.surface),
+                field(lemma),
+                self.upos.get(pos),
+                field(&xpos.join("-")),
+                misc.join("|"),
+            )?;
+        }
+        writeln!(out)
+    }
+}
+
+/// Escapes a field, which may not be empty nor contain tabs and newlines.
+fn field(s: &str) -> String {
+    if s.is_empty() {
+        "_".to_string()
+    } else {
+        s.replace(['\t', '\n', '\r'], " ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(surface: &str, feature: &str, start: usize, end: usize) -> Token {
+        Token {
+            surface: surface.to_string(),
+            feature: feature.to_string(),
+            start,
+            end,
+            posid: 0,
+            unknown: false,
+        }
+    }
+
+    #[test]
+    fn test_tables() {
+        let ipadic = UposMap::ipadic();
+        let cases = [
+            ("名詞,一般,*,*", "NOUN"),
+            ("名詞,固有名詞,人名,名", "PROPN"),
+            ("名詞,接尾,助動詞語幹,*", "AUX"),
+            ("動詞,非自立,*,*", "AUX"),
+            ("助詞,接続助詞,*,*", "SCONJ"),
+            ("記号,句点,*,*", "PUNCT"),
+            ("記号,一般,*,*", "SYM"),
+        ];
+        for &(pos, upos) in &cases {
+            assert_eq!(ipadic.get(&split_features(pos)), upos, "{}", pos);
+        }
+        let unidic = UposMap::unidic();
+        let cases = [
+            ("名詞,普通名詞,一般,*", "NOUN"),
+            ("代名詞,*,*,*", "PRON"),
+            ("接尾辞,形状詞的,*,*", "ADJ"),
+            ("補助記号,読点,*,*", "PUNCT"),
+        ];
+        for &(pos, upos) in &cases {
+            assert_eq!(unidic.get(&split_features(pos)), upos, "{}", pos);
+        }
+    }
+
+    #[test]
+    fn test_longest_prefix() {
+        let map = UposMap::parse("# comment\n\n名詞\tNOUN\r\n名詞,*,人名\tPROPN\n名詞,数\tNUM\n")
+            .unwrap();
+        assert_eq!(map.get(&["名詞", "一般"]), "NOUN");
+        assert_eq!(map.get(&["名詞", "固有名詞", "人名", "名"]), "PROPN");
+        assert_eq!(map.get(&["名詞", "数", "人名"]), "PROPN");
+        assert_eq!(map.get(&["名詞", "数"]), "NUM");
+        assert_eq!(map.get(&["動詞", "自立"]), "X");
+        assert_eq!(map.get(&[]), "X");
+
+        let mut map = map;
+        map.insert(&["名詞"], "PROPN");
+        assert_eq!(map.get(&["名詞", "一般"]), "PROPN");
+        assert_eq!(UposMap::new().get(&["名詞"]), "X");
+    }
+
+    #[test]
+    fn test_malformed_table() {
+        for &(table, line, content) in &[
+            ("名詞\tNOUN\n動詞\n", 2, "動詞"),
+            ("名詞\t\n", 1, "名詞\t"),
+            ("#\n名詞\tNOUN\tX\n", 2, "名詞\tNOUN\tX"),
+        ] {
+            assert_eq!(
+                UposMap::parse(table),
+                Err(UposTableError {
+                    line,
+                    content: content.to_string(),
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn test_write_tokens() {
+        let text = "太郎は 本。";
+        let tokens = [
+            token("太郎", "名詞,固有名詞,人名,名,*,*,太郎,タロウ,タロー", 0, 6),
+            token("は", "助詞,係助詞,*,*,*,*,は,ハ,ワ", 6, 9),
+            token("本", "名詞,一般,*,*,*,*", 10, 13),
+            token("。", "記号,句点,*,*,*,*,。,。,。", 13, 16),
+        ];
+        let mut out = Vec::new();
+        ConlluExporter::ipadic()
+            .write_tokens(&mut out, Some("s1"), text, &tokens)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "# sent_id = s1\n\
+             # text = 太郎は 本。\n\
+             1\t太郎\t太郎\tPROPN\t名詞-固有名詞-人名-名\t_\t_\t_\t_\tSpaceAfter=No|ByteRange=0:6\n\
+             2\tは\tは\tADP\t助詞-係助詞\t_\t_\t_\t_\tByteRange=6:9\n\
+             3\t本\t本\tNOUN\t名詞-一般\t_\t_\t_\t_\tSpaceAfter=No|ByteRange=10:13\n\
+             4\t。\t。\tPUNCT\t記号-句点\t_\t_\t_\t_\tByteRange=13:16\n\
+             \n"
+        );
+    }
+}
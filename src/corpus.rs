@@ -0,0 +1,352 @@
+//! Annotated corpora
+//!
+//! MeCab's annotated corpus format, used by `mecab-cost-train` and `mecab-system-eval`, is the
+//! output format of `mecab` itself: each morpheme is a line of the surface and the feature
+//! string separated by a tab (shown as `\t` below), and each sentence ends with an `EOS` line.
+//!
+//! ```text
+//! 太郎\t名詞,固有名詞,人名,名,*,*,太郎,タロウ,タロー
+//! が\t助詞,格助詞,一般,*,*,*,が,ガ,ガ
+//! 来る\t動詞,自立,*,*,カ変・来ル,基本形,来る,クル,クル
+//! EOS
+//! ```
+//!
+//! [`CorpusReader`](struct.CorpusReader.html) and [`CorpusWriter`](struct.CorpusWriter.html)
+//! convert between the format and [`Morpheme`](struct.Morpheme.html)s, and
+//! [`sentence_text`](fn.sentence_text.html) recovers the raw text, as `mecab-test-gen` does.
+//! [`test_gen`](fn.test_gen.html) runs `mecab-test-gen` itself on Unix.
+
+use std::error;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::str::{self, Utf8Error};
+use tokenizer::Token;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+#[cfg(unix)]
+use std::path::Path;
+#[cfg(unix)]
+use tools::{self, require_file, Tool, ToolError};
+
+/// A morpheme of an annotated sentence.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Morpheme {
+    /// The surface form.
+    pub surface: String,
+
+    /// The feature string, e.g., `名詞,固有名詞,人名,名,*,*,太郎,タロウ,タロー`.
+    pub feature: String,
+}
+
+impl Morpheme {
+    pub fn new(surface: &str, feature: &str) -> Self {
+        Morpheme {
+            surface: surface.to_string(),
+            feature: feature.to_string(),
+        }
+    }
+}
+
+impl<'a> From<&'a Token> for Morpheme {
+    fn from(token: &'a Token) -> Self {
+        Morpheme::new(&token.surface, &token.feature)
+    }
+}
+
+/// Return the raw text of an annotated sentence, i.e., the concatenation of the surfaces.
+pub fn sentence_text(sentence: &[Morpheme]) -> String {
+    sentence.iter().map(|m| m.surface.as_str()).collect()
+}
+
+/// Iterator reading sentences from an annotated corpus.
+///
+/// Blank lines are ignored, and a sentence lacking `EOS` at the end of the input is also
+/// yielded.
+///
+/// ```rust,no_run
+/// use metalcab::corpus::{sentence_text, CorpusReader};
+/// use std::fs::File;
+/// use std::io::BufReader;
+///
+/// let file = File::open("corpus.txt").unwrap();
+/// for sentence in CorpusReader::new(BufReader::new(file)) {
+///     println!("{}", sentence_text(&sentence.unwrap()));
+/// }
+/// ```
+#[derive(Debug)]
+pub struct CorpusReader<R> {
+    reader: R,
+    line: Vec<u8>,
+    lineno: usize,
+    done: bool,
+}
+
+impl<R: BufRead> CorpusReader<R> {
+    pub fn new(reader: R) -> Self {
+        CorpusReader {
+            reader,
+            line: Vec::new(),
+            lineno: 0,
+            done: false,
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Reads the next sentence, returning `None` at the end of the input.
+    fn read_sentence(&mut self) -> Result<Option<Vec<Morpheme>>, CorpusError> {
+        let mut sentence = Vec::new();
+        loop {
+            self.line.clear();
+            if self.reader.read_until(b'\n', &mut self.line)? == 0 {
+                self.done = true;
+                return Ok(if sentence.is_empty() {
+                    None
+                } else {
+                    Some(sentence)
+                });
+            }
+            self.lineno += 1;
+            let line = str::from_utf8(&self.line).map_err(|error| CorpusError::InvalidUtf8 {
+                line: self.lineno,
+                error,
+            })?;
+            let line = line.trim_end_matches(['\n', '\r']);
+            if line == "EOS" {
+                return Ok(Some(sentence));
+            }
+            if line.trim().is_empty() {
+                continue;
+            }
+            match line.find('\t') {
+                Some(pos) => sentence.push(Morpheme::new(&line[..pos], &line[pos + 1..])),
+                None => {
+                    return Err(CorpusError::Malformed {
+                        line: self.lineno,
+                        content: line.to_string(),
+                    })
+                }
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for CorpusReader<R> {
+    type Item = Result<Vec<Morpheme>, CorpusError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        self.read_sentence().transpose()
+    }
+}
+
+/// Writes sentences in the annotated corpus format.
+#[derive(Debug)]
+pub struct CorpusWriter<W> {
+    writer: W,
+}
+
+impl<W: Write> CorpusWriter<W> {
+    pub fn new(writer: W) -> Self {
+        CorpusWriter { writer }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// Writes a sentence followed by `EOS`.
+    ///
+    /// Fails with `ErrorKind::InvalidInput` if a surface contains a tab or a newline, or a
+    /// feature string contains a newline, which cannot be represented in the format.
+    pub fn write_sentence(&mut self, sentence: &[Morpheme]) -> io::Result<()> {
+        for m in sentence {
+            if m.surface.contains(['\t', '\n', '\r']) || m.feature.contains(['\n', '\r']) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("cannot write the morpheme {:?}", m.surface),
+                ));
+            }
+        }
+        for m in sentence {
+            writeln!(self.writer, "{}\t{}", m.surface, m.feature)?;
+        }
+        writeln!(self.writer, "EOS")
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Error from [`CorpusReader`](struct.CorpusReader.html).
+#[derive(Debug)]
+pub enum CorpusError {
+    Io(io::Error),
+    /// A line is not valid UTF-8.
+    InvalidUtf8 {
+        /// The 1-based line number.
+        line: usize,
+        error: Utf8Error,
+    },
+    /// A line is neither a morpheme nor `EOS`.
+    Malformed {
+        /// The 1-based line number.
+        line: usize,
+        content: String,
+    },
+}
+
+impl fmt::Display for CorpusError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CorpusError::Io(ref e) => write!(f, "I/O error: {}", e),
+            CorpusError::InvalidUtf8 { line, ref error } => {
+                write!(f, "invalid UTF-8 at line {}: {}", line, error)
+            }
+            CorpusError::Malformed { line, ref content } => {
+                write!(f, "malformed line {}: {:?}", line, content)
+            }
+        }
+    }
+}
+
+impl error::Error for CorpusError {}
+
+impl From<io::Error> for CorpusError {
+    fn from(e: io::Error) -> Self {
+        CorpusError::Io(e)
+    }
+}
+
+/// Runs `mecab-test-gen` on annotated corpus files, returning the raw text of each sentence.
+///
+/// This is only available on Unix.
+#[cfg(unix)]
+pub fn test_gen<I, S>(files: I) -> Result<Vec<String>, ToolError>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<Path>,
+{
+    let files: Vec<_> = files
+        .into_iter()
+        .map(|file| file.as_ref().to_path_buf())
+        .collect();
+    if files.is_empty() {
+        return Err(ToolError::InvalidArgument(
+            "no corpus file is given".to_string(),
+        ));
+    }
+    for file in &files {
+        require_file(file)?;
+    }
+    let output = tools::run(Tool::TestGen, &files, b"")?.check()?;
+    let stdout = String::from_utf8(output.stdout)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok(stdout.lines().map(str::to_string).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(input: &str) -> Vec<Vec<Morpheme>> {
+        CorpusReader::new(input.as_bytes())
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_read_sentences() {
+        let input = "太郎\t名詞,固有名詞\nが\t助詞,格助詞\nEOS\n来る\t動詞,自立\nEOS\n";
+        assert_eq!(
+            read(input),
+            vec![
+                vec![
+                    Morpheme::new("太郎", "名詞,固有名詞"),
+                    Morpheme::new("が", "助詞,格助詞"),
+                ],
+                vec![Morpheme::new("来る", "動詞,自立")],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_read_eos() {
+        // Consecutive EOS lines are empty sentences, and CRLF is accepted.
+        assert_eq!(
+            read("EOS\r\nあ\t感動詞\r\nEOS\r\nEOS"),
+            vec![vec![], vec![Morpheme::new("あ", "感動詞")], vec![]]
+        );
+        // A sentence without EOS at the end of the input is yielded.
+        assert_eq!(
+            read("あ\t感動詞\nEOS\nい\t名詞"),
+            vec![
+                vec![Morpheme::new("あ", "感動詞")],
+                vec![Morpheme::new("い", "名詞")],
+            ]
+        );
+        assert!(read("").is_empty());
+    }
+
+    #[test]
+    fn test_read_blank_lines() {
+        assert_eq!(
+            read("\nあ\t感動詞\n  \n\nい\t名詞\nEOS\n\n"),
+            vec![vec![
+                Morpheme::new("あ", "感動詞"),
+                Morpheme::new("い", "名詞"),
+            ]]
+        );
+    }
+
+    #[test]
+    fn test_read_tabs_and_commas() {
+        // The surface ends at the first tab, and commas are part of the surface.
+        assert_eq!(
+            read("1,000\t名詞,数\na\tb\tc\nEOS\n"),
+            vec![vec![
+                Morpheme::new("1,000", "名詞,数"),
+                Morpheme::new("a", "b\tc"),
+            ]]
+        );
+    }
+
+    #[test]
+    fn test_read_malformed() {
+        let mut reader = CorpusReader::new("あ\t感動詞\nい\nEOS\n".as_bytes());
+        match reader.next() {
+            Some(Err(CorpusError::Malformed { line, content })) => {
+                assert_eq!((line, content.as_str()), (2, "い"));
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_write_sentence() {
+        let mut writer = CorpusWriter::new(Vec::new());
+        let sentence = vec![
+            Morpheme::new("1,000", "名詞,数"),
+            Morpheme::new("円", "名詞"),
+        ];
+        writer.write_sentence(&sentence).unwrap();
+        let output = writer.into_inner();
+        assert_eq!(output, "1,000\t名詞,数\n円\t名詞\nEOS\n".as_bytes());
+        assert_eq!(read(str::from_utf8(&output).unwrap()), vec![sentence]);
+
+        let mut writer = CorpusWriter::new(Vec::new());
+        let error = writer
+            .write_sentence(&[Morpheme::new("a\tb", "名詞")])
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(writer.into_inner().is_empty());
+    }
+}
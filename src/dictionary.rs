@@ -0,0 +1,663 @@
+//! Building dictionaries
+//!
+//! [`DictionaryCompiler`](struct.DictionaryCompiler.html) is a typed front end of
+//! `mecab-dict-index`, which compiles CSV sources into binary dictionaries.
+//! [`DictionaryGenerator`](struct.DictionaryGenerator.html) generates the sources from a
+//! model trained with [`CostTrainer`][train::CostTrainer].
+//! [`UserDictionaryBuilder`](struct.UserDictionaryBuilder.html) builds a user dictionary for
+//! a loaded [`Model`][compat::Model] from typed entries.
+//!
+//! This module is only available on Unix.
+//!
+//! [compat::Model]: ../compat/struct.Model.html
+//! [train::CostTrainer]: ../train/struct.CostTrainer.html
+
+use compat::{DictionaryType, MecabError, Model};
+use std::env;
+use std::error;
+use std::ffi::{CString, OsStr, OsString};
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tools::{self, require_dir, require_file, require_parent_dir, Tool, ToolError, ToolOutput};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Target {
+    /// Build `sys.dic`, `unk.dic`, `matrix.bin` and `char.bin` into the directory.
+    System(PathBuf),
+    /// Build a user dictionary from the CSV files.
+    User {
+        output: PathBuf,
+        sources: Vec<PathBuf>,
+    },
+}
+
+/// Compiles dictionaries with `mecab-dict-index`.
+///
+/// ```rust,no_run
+/// use metalcab::dictionary::DictionaryCompiler;
+///
+/// DictionaryCompiler::user("/usr/lib/mecab/dic/ipadic", "user.dic", &["user.csv"])
+///     .input_charset("utf-8")
+///     .output_charset("utf-8")
+///     .compile()
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DictionaryCompiler {
+    dicdir: PathBuf,
+    target: Target,
+    input_charset: Option<String>,
+    output_charset: Option<String>,
+    model: Option<PathBuf>,
+}
+
+impl DictionaryCompiler {
+    /// Compiles the system dictionary from the sources in `source_dir`, i.e., the CSV files,
+    /// `matrix.def`, `char.def`, `unk.def` and so on, into `output_dir`.
+    pub fn system<P: AsRef<Path>, Q: AsRef<Path>>(source_dir: P, output_dir: Q) -> Self {
+        DictionaryCompiler::new(
+            source_dir,
+            Target::System(output_dir.as_ref().to_path_buf()),
+        )
+    }
+
+    /// Compiles a user dictionary from the CSV files `sources` into `output`, e.g.,
+    /// `user.dic`. `dicdir` is the directory of the system dictionary it is used with.
+    pub fn user<P, Q, I, S>(dicdir: P, output: Q, sources: I) -> Self
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+        I: IntoIterator<Item = S>,
+        S: AsRef<Path>,
+    {
+        let target = Target::User {
+            output: output.as_ref().to_path_buf(),
+            sources: sources
+                .into_iter()
+                .map(|source| source.as_ref().to_path_buf())
+                .collect(),
+        };
+        DictionaryCompiler::new(dicdir, target)
+    }
+
+    fn new<P: AsRef<Path>>(dicdir: P, target: Target) -> Self {
+        DictionaryCompiler {
+            dicdir: dicdir.as_ref().to_path_buf(),
+            target,
+            input_charset: None,
+            output_charset: None,
+            model: None,
+        }
+    }
+
+    /// Sets the charset of the CSV sources (`-f`). MeCab defaults to EUC-JP.
+    pub fn input_charset(mut self, charset: &str) -> Self {
+        self.input_charset = Some(charset.to_string());
+        self
+    }
+
+    /// Sets the charset of the binary dictionary (`-t`). MeCab defaults to EUC-JP.
+    pub fn output_charset(mut self, charset: &str) -> Self {
+        self.output_charset = Some(charset.to_string());
+        self
+    }
+
+    /// Sets the model file (`-m`) used to assign costs to entries lacking them.
+    pub fn model<P: AsRef<Path>>(mut self, model: P) -> Self {
+        self.model = Some(model.as_ref().to_path_buf());
+        self
+    }
+
+    /// Return the path of the compiled dictionary, i.e., `sys.dic` or the user dictionary.
+    pub fn output(&self) -> PathBuf {
+        match self.target {
+            Target::System(ref output_dir) => output_dir.join("sys.dic"),
+            Target::User { ref output, .. } => output.clone(),
+        }
+    }
+
+    /// Return the arguments passed to `mecab-dict-index`, excluding `argv[0]`.
+    pub fn args(&self) -> Vec<OsString> {
+        let mut args = vec![OsString::from("-d"), self.dicdir.clone().into_os_string()];
+        if let Some(ref charset) = self.input_charset {
+            args.push("-f".into());
+            args.push(charset.into());
+        }
+        if let Some(ref charset) = self.output_charset {
+            args.push("-t".into());
+            args.push(charset.into());
+        }
+        if let Some(ref model) = self.model {
+            args.push("-m".into());
+            args.push(model.clone().into_os_string());
+        }
+        match self.target {
+            Target::System(ref output_dir) => {
+                args.push("-o".into());
+                args.push(output_dir.clone().into_os_string());
+            }
+            Target::User {
+                ref output,
+                ref sources,
+            } => {
+                args.push("-u".into());
+                args.push(output.clone().into_os_string());
+                args.extend(sources.iter().map(|source| source.clone().into_os_string()));
+            }
+        }
+        args
+    }
+
+    /// Checks that the inputs exist, so that missing files are reported as
+    /// [`ToolError::NotFound`](../tools/enum.ToolError.html#variant.NotFound).
+    pub fn validate(&self) -> Result<(), ToolError> {
+        require_dir(&self.dicdir)?;
+        if let Some(ref model) = self.model {
+            require_file(model)?;
+        }
+        match self.target {
+            Target::System(ref output_dir) => {
+                for name in &["matrix.def", "char.def", "unk.def"] {
+                    require_file(&self.dicdir.join(name))?;
+                }
+                require_dir(output_dir)?;
+            }
+            Target::User {
+                ref output,
+                ref sources,
+            } => {
+                if sources.is_empty() {
+                    return Err(ToolError::InvalidArgument(
+                        "no source is given for the user dictionary".to_string(),
+                    ));
+                }
+                for source in sources {
+                    require_file(source)?;
+                }
+                require_parent_dir(output)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Compiles the dictionary, returning the messages printed by `mecab-dict-index`.
+    pub fn compile(&self) -> Result<ToolOutput, ToolError> {
+        self.validate()?;
+        let output = tools::run(Tool::DictIndex, self.args(), b"")?.check()?;
+        require_file(&self.output())?;
+        Ok(output)
+    }
+}
+
+/// Generates dictionary sources from a trained model with `mecab-dict-gen`.
+///
+/// The generated sources, i.e., the CSV files with costs assigned, `matrix.def`, `dicrc` and
+/// so on, are then compiled with [`compiler`](#method.compiler).
+///
+/// ```rust,no_run
+/// use metalcab::dictionary::DictionaryGenerator;
+///
+/// let generator = DictionaryGenerator::new("seed", "model", "final");
+/// generator.generate().unwrap();
+/// generator.compiler().compile().unwrap();
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DictionaryGenerator {
+    dicdir: PathBuf,
+    model: PathBuf,
+    output_dir: PathBuf,
+}
+
+impl DictionaryGenerator {
+    /// Generates the sources into `output_dir` from the seed dictionary in `dicdir` and
+    /// `model`, the output of `mecab-cost-train`.
+    pub fn new<P, Q, R>(dicdir: P, model: Q, output_dir: R) -> Self
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+        R: AsRef<Path>,
+    {
+        DictionaryGenerator {
+            dicdir: dicdir.as_ref().to_path_buf(),
+            model: model.as_ref().to_path_buf(),
+            output_dir: output_dir.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn output_dir(&self) -> &Path {
+        &self.output_dir
+    }
+
+    /// Return the arguments passed to `mecab-dict-gen`, excluding `argv[0]`.
+    pub fn args(&self) -> Vec<OsString> {
+        vec![
+            OsString::from("-d"),
+            self.dicdir.clone().into_os_string(),
+            OsString::from("-m"),
+            self.model.clone().into_os_string(),
+            OsString::from("-o"),
+            self.output_dir.clone().into_os_string(),
+        ]
+    }
+
+    /// Checks that the inputs exist, so that missing files are reported as
+    /// [`ToolError::NotFound`](../tools/enum.ToolError.html#variant.NotFound).
+    pub fn validate(&self) -> Result<(), ToolError> {
+        require_dir(&self.dicdir)?;
+        require_file(&self.model)?;
+        require_dir(&self.output_dir)
+    }
+
+    /// Generates the sources, returning the messages printed by `mecab-dict-gen`.
+    pub fn generate(&self) -> Result<ToolOutput, ToolError> {
+        self.validate()?;
+        let output = tools::run(Tool::DictGen, self.args(), b"")?.check()?;
+        require_file(&self.output_dir.join("matrix.def"))?;
+        Ok(output)
+    }
+
+    /// Return a compiler building the system dictionary from the generated sources in place.
+    pub fn compiler(&self) -> DictionaryCompiler {
+        DictionaryCompiler::system(&self.output_dir, &self.output_dir)
+    }
+}
+
+/// How the context IDs of a [`UserEntry`](struct.UserEntry.html) are determined.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ContextIds {
+    /// Explicit left and right context IDs.
+    Ids { left: u16, right: u16 },
+
+    /// The IDs defined for the part of speech in `left-id.def` and `right-id.def`, e.g.,
+    /// `["名詞", "固有名詞", "人名", "姓"]`. Omitted trailing fields match `*`.
+    Pos(Vec<String>),
+
+    /// Let `mecab-dict-index` assign the IDs from the features. This requires a model file.
+    Auto,
+}
+
+/// An entry of a user dictionary.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct UserEntry {
+    /// The surface form.
+    pub surface: String,
+
+    pub context: ContextIds,
+
+    /// The word cost. `None` lets `mecab-dict-index` assign it, which requires a model file.
+    pub cost: Option<i16>,
+
+    /// The feature fields, e.g., `["名詞", "固有名詞", "人名", "姓", "*", "*", "工藤",
+    /// "クドウ", "クドー"]` for the IPA dictionary.
+    pub features: Vec<String>,
+}
+
+impl UserEntry {
+    /// Creates an entry whose context IDs are looked up by the leading features.
+    ///
+    /// `pos_fields` is the number of features forming the part of speech, e.g., `4` for
+    /// the IPA dictionary and `6` for UniDic.
+    pub fn with_pos(
+        surface: &str,
+        features: &[&str],
+        pos_fields: usize,
+        cost: Option<i16>,
+    ) -> Self {
+        let pos_fields = pos_fields.min(features.len());
+        UserEntry {
+            surface: surface.to_string(),
+            context: ContextIds::Pos(
+                features[..pos_fields]
+                    .iter()
+                    .map(|f| f.to_string())
+                    .collect(),
+            ),
+            cost,
+            features: features.iter().map(|f| f.to_string()).collect(),
+        }
+    }
+}
+
+/// Error from [`UserDictionaryBuilder`](struct.UserDictionaryBuilder.html).
+#[derive(Debug)]
+pub enum BuildError {
+    Io(io::Error),
+    Mecab(MecabError),
+    Tool(ToolError),
+    /// The model has no system dictionary, or its directory is unknown.
+    NoSystemDictionary,
+    /// An entry is rejected.
+    InvalidEntry {
+        /// The position of the entry in the builder.
+        index: usize,
+        reason: String,
+    },
+    /// No context ID is defined for the part of speech.
+    UnknownPos(Vec<String>),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BuildError::Io(ref e) => write!(f, "I/O error: {}", e),
+            BuildError::Mecab(ref e) => write!(f, "MeCab error: {:?}", e),
+            BuildError::Tool(ref e) => write!(f, "mecab-dict-index: {}", e),
+            BuildError::NoSystemDictionary => write!(f, "the system dictionary is not found"),
+            BuildError::InvalidEntry { index, ref reason } => {
+                write!(f, "invalid entry #{}: {}", index, reason)
+            }
+            BuildError::UnknownPos(ref pos) => {
+                write!(f, "no context ID for the part of speech {}", pos.join(","))
+            }
+        }
+    }
+}
+
+impl error::Error for BuildError {}
+
+impl From<io::Error> for BuildError {
+    fn from(e: io::Error) -> Self {
+        BuildError::Io(e)
+    }
+}
+
+impl From<MecabError> for BuildError {
+    fn from(e: MecabError) -> Self {
+        BuildError::Mecab(e)
+    }
+}
+
+impl From<ToolError> for BuildError {
+    fn from(e: ToolError) -> Self {
+        BuildError::Tool(e)
+    }
+}
+
+/// Builds a user dictionary for a loaded model from typed entries.
+///
+/// Entries are checked against the system dictionary of the model, and then compiled with
+/// `mecab-dict-index` in the directory of the system dictionary.
+///
+/// ```rust,no_run
+/// use metalcab::compat::Model;
+/// use metalcab::dictionary::{UserDictionaryBuilder, UserEntry};
+/// use std::ffi::CStr;
+///
+/// let model = Model::create2(CStr::from_bytes_with_nul(b"\0").unwrap()).unwrap();
+/// let mut builder = UserDictionaryBuilder::new(&model).unwrap();
+/// builder
+///     .add(UserEntry::with_pos(
+///         "メタルキャブ",
+///         &["名詞", "固有名詞", "組織", "*", "*", "*", "メタルキャブ", "メタルキャブ", "メタルキャブ"],
+///         4,
+///         Some(5000),
+///     ))
+///     .unwrap();
+/// let model = builder.build_and_load("user.dic").unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct UserDictionaryBuilder {
+    dicdir: PathBuf,
+    charset: String,
+    lsize: u32,
+    rsize: u32,
+    model_file: Option<PathBuf>,
+    left_ids: Vec<(u16, Vec<String>)>,
+    right_ids: Vec<(u16, Vec<String>)>,
+    entries: Vec<UserEntry>,
+}
+
+impl UserDictionaryBuilder {
+    /// Creates a builder for the system dictionary of `model`.
+    pub fn new(model: &Model) -> Result<Self, BuildError> {
+        let mut info = model.dictionary_info();
+        while let Some(i) = info {
+            if i.type_() == DictionaryType::MECAB_SYS_DIC {
+                break;
+            }
+            info = i.next();
+        }
+        let info = info.ok_or(BuildError::NoSystemDictionary)?;
+        let filename = info.filename().ok_or(BuildError::NoSystemDictionary)?;
+        let dicdir = Path::new(OsStr::from_bytes(filename))
+            .parent()
+            .ok_or(BuildError::NoSystemDictionary)?
+            .to_path_buf();
+        let charset = info.charset().map_or("utf-8".to_string(), |c| {
+            String::from_utf8_lossy(c).into_owned()
+        });
+        Ok(UserDictionaryBuilder {
+            left_ids: read_id_def(&dicdir.join("left-id.def"))?,
+            right_ids: read_id_def(&dicdir.join("right-id.def"))?,
+            dicdir,
+            charset,
+            lsize: info.lsize(),
+            rsize: info.rsize(),
+            model_file: None,
+            entries: Vec::new(),
+        })
+    }
+
+    /// Return the directory of the system dictionary.
+    pub fn dicdir(&self) -> &Path {
+        &self.dicdir
+    }
+
+    /// Sets the model file, e.g., `mecab-ipadic-2.7.0-20070801.model`, used to assign costs
+    /// and context IDs left unspecified.
+    pub fn model_file<P: AsRef<Path>>(&mut self, model_file: P) -> &mut Self {
+        self.model_file = Some(model_file.as_ref().to_path_buf());
+        self
+    }
+
+    /// Return the entries added so far.
+    pub fn entries(&self) -> &[UserEntry] {
+        &self.entries
+    }
+
+    /// Validates and adds an entry.
+    pub fn add(&mut self, entry: UserEntry) -> Result<&mut Self, BuildError> {
+        let index = self.entries.len();
+        let invalid = |reason: &str| BuildError::InvalidEntry {
+            index,
+            reason: reason.to_string(),
+        };
+        if entry.surface.is_empty() {
+            return Err(invalid("the surface is empty"));
+        }
+        if entry.features.is_empty() {
+            return Err(invalid("no feature is given"));
+        }
+        let mut fields = Some(&entry.surface).into_iter().chain(&entry.features);
+        if fields.any(|f| f.contains(['\n', '\r', '"'])) {
+            return Err(invalid("a field contains a newline or a double quote"));
+        }
+        match entry.context {
+            ContextIds::Ids { left, right } => {
+                if u32::from(left) >= self.lsize {
+                    return Err(invalid(&format!(
+                        "left ID {} >= lsize {}",
+                        left, self.lsize
+                    )));
+                }
+                if u32::from(right) >= self.rsize {
+                    return Err(invalid(&format!(
+                        "right ID {} >= rsize {}",
+                        right, self.rsize
+                    )));
+                }
+            }
+            ContextIds::Pos(ref pos) => {
+                self.resolve_pos(pos)?;
+            }
+            ContextIds::Auto => {}
+        }
+        let needs_model = entry.cost.is_none() || entry.context == ContextIds::Auto;
+        if needs_model && self.model_file.is_none() {
+            return Err(invalid(
+                "the cost or the context IDs are unspecified, but no model file is set",
+            ));
+        }
+        self.entries.push(entry);
+        Ok(self)
+    }
+
+    /// Looks up the left and right context IDs of a part of speech.
+    pub fn resolve_pos(&self, pos: &[String]) -> Result<(u16, u16), BuildError> {
+        let left = find_id(&self.left_ids, pos);
+        let right = find_id(&self.right_ids, pos);
+        match (left, right) {
+            (Some(left), Some(right)) => Ok((left, right)),
+            _ => Err(BuildError::UnknownPos(pos.to_vec())),
+        }
+    }
+
+    /// Formats the entries as CSV accepted by `mecab-dict-index`.
+    pub fn to_csv(&self) -> Result<String, BuildError> {
+        let mut csv = String::new();
+        for entry in &self.entries {
+            let (left, right) = match entry.context {
+                ContextIds::Ids { left, right } => (left.to_string(), right.to_string()),
+                ContextIds::Pos(ref pos) => {
+                    let (left, right) = self.resolve_pos(pos)?;
+                    (left.to_string(), right.to_string())
+                }
+                ContextIds::Auto => (String::new(), String::new()),
+            };
+            let cost = entry.cost.map_or(String::new(), |cost| cost.to_string());
+            let fields = [&entry.surface, &left, &right, &cost]
+                .iter()
+                .map(|field| field.as_str())
+                .chain(entry.features.iter().map(|field| field.as_str()))
+                .map(quote_csv)
+                .collect::<Vec<_>>();
+            csv.push_str(&fields.join(","));
+            csv.push('\n');
+        }
+        Ok(csv)
+    }
+
+    /// Compiles the entries into the user dictionary `output`.
+    pub fn build<P: AsRef<Path>>(&self, output: P) -> Result<ToolOutput, BuildError> {
+        let csv = TempFile::create("csv", self.to_csv()?.as_bytes())?;
+        let mut compiler = DictionaryCompiler::user(&self.dicdir, output, [&csv.0])
+            .input_charset("utf-8")
+            .output_charset(&self.charset);
+        if let Some(ref model_file) = self.model_file {
+            compiler = compiler.model(model_file);
+        }
+        Ok(compiler.compile()?)
+    }
+
+    /// Compiles the entries into the user dictionary `output`, and loads a new model with the
+    /// system dictionary and it.
+    pub fn build_and_load<P: AsRef<Path>>(&self, output: P) -> Result<Model, BuildError> {
+        self.build(output.as_ref())?;
+        let args = [
+            OsStr::new("mecab"),
+            OsStr::new("-d"),
+            self.dicdir.as_os_str(),
+            OsStr::new("-u"),
+            output.as_ref().as_os_str(),
+        ];
+        let args = args
+            .iter()
+            .map(|arg| CString::new(arg.as_bytes()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(MecabError::from)?;
+        let args = args.iter().map(|arg| arg.as_c_str()).collect::<Vec<_>>();
+        Ok(Model::create(&args)?)
+    }
+}
+
+/// Reads `left-id.def` or `right-id.def`, which consists of lines like
+/// `1285 名詞,一般,*,*,*,*,*`.
+fn read_id_def(path: &Path) -> Result<Vec<(u16, Vec<String>)>, BuildError> {
+    let file = File::open(path).map_err(|e| {
+        if e.kind() == io::ErrorKind::NotFound {
+            BuildError::Tool(ToolError::NotFound(path.to_path_buf()))
+        } else {
+            BuildError::Io(e)
+        }
+    })?;
+    let mut ids = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        let mut parts = line.trim().splitn(2, ' ');
+        let id = parts.next().and_then(|id| id.parse().ok());
+        if let (Some(id), Some(features)) = (id, parts.next()) {
+            ids.push((id, features.split(',').map(str::to_string).collect()));
+        }
+    }
+    Ok(ids)
+}
+
+/// Finds the ID whose features match `pos`, preferring ones without further specialization.
+fn find_id(ids: &[(u16, Vec<String>)], pos: &[String]) -> Option<u16> {
+    let matches = |features: &[String]| {
+        pos.iter()
+            .enumerate()
+            .all(|(i, field)| features.get(i).map_or(field == "*", |f| f == field))
+    };
+    let generic = |features: &[String]| features.iter().skip(pos.len()).all(|f| f == "*");
+    ids.iter()
+        .find(|(_, features)| matches(features) && generic(features))
+        .or_else(|| ids.iter().find(|(_, features)| matches(features)))
+        .map(|&(id, _)| id)
+}
+
+fn quote_csv(field: &str) -> String {
+    if field.contains(',') {
+        format!("\"{}\"", field)
+    } else {
+        field.to_string()
+    }
+}
+
+/// A file in the temporary directory, removed on drop.
+struct TempFile(PathBuf);
+
+impl TempFile {
+    /// Creates a new file readable only by the user and writes `contents` to it.
+    ///
+    /// The name is predictable, so an existing file or symlink planted by someone else is
+    /// never opened; another name is tried instead.
+    fn create(extension: &str, contents: &[u8]) -> io::Result<Self> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        loop {
+            let name = format!(
+                "metalcab-{}-{}.{}",
+                process::id(),
+                COUNTER.fetch_add(1, Ordering::SeqCst),
+                extension
+            );
+            let path = env::temp_dir().join(name);
+            let file = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(0o600)
+                .open(&path);
+            match file {
+                Ok(mut file) => {
+                    let temp = TempFile(path);
+                    file.write_all(contents)?;
+                    return Ok(temp);
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
@@ -0,0 +1,357 @@
+//! Word-cost estimation for new dictionary entries
+//!
+//! [`CostEstimator`](struct.CostEstimator.html) chooses the cost of a candidate entry from
+//! example sentences, so that the entry is chosen where it should be without over-segmenting
+//! elsewhere.
+//!
+//! For each occurrence of the surface in an example, the sentence is parsed twice: as is, and
+//! with token boundaries forced around the occurrence. Splicing the candidate into the
+//! constrained best path, using [`Model::transition_cost`][compat::Model::transition_cost]
+//! for the connections, gives the cost below which the candidate beats the current best path.
+//!
+//! [compat::Model::transition_cost]: ../compat/struct.Model.html#method.transition_cost
+
+use compat::{BoundaryConstraintType, DictionaryType, Lattice, MecabError, Model, Tagger};
+use raw::{MECAB_BOS_NODE, MECAB_EOS_NODE, MECAB_NOR_NODE};
+use std::error;
+use std::ffi::{CStr, CString};
+use std::fmt;
+
+/// Error from [`CostEstimator`](struct.CostEstimator.html).
+#[derive(Debug)]
+pub enum EstimateError {
+    Mecab(MecabError),
+    /// The candidate is rejected before parsing any example.
+    InvalidCandidate(String),
+}
+
+impl fmt::Display for EstimateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EstimateError::Mecab(ref e) => write!(f, "MeCab error: {:?}", e),
+            EstimateError::InvalidCandidate(ref reason) => {
+                write!(f, "invalid candidate: {}", reason)
+            }
+        }
+    }
+}
+
+impl error::Error for EstimateError {}
+
+impl From<MecabError> for EstimateError {
+    fn from(e: MecabError) -> Self {
+        EstimateError::Mecab(e)
+    }
+}
+
+/// A candidate entry whose cost is to be estimated.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Candidate {
+    pub surface: String,
+
+    /// The left context ID.
+    pub left: u16,
+
+    /// The right context ID.
+    pub right: u16,
+}
+
+/// An entry with the same surface which already exists in the dictionary.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ExistingEntry {
+    pub feature: String,
+    pub left: u16,
+    pub right: u16,
+    pub cost: i64,
+}
+
+/// The result of estimation for an occurrence of the surface in an example sentence.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ExampleReport {
+    /// The example sentence.
+    pub text: String,
+
+    /// The byte offset of the occurrence.
+    pub offset: usize,
+
+    /// Whether the candidate should be chosen at the occurrence.
+    pub positive: bool,
+
+    /// The candidate is chosen at the occurrence if and only if its cost is below this value.
+    pub threshold: i64,
+
+    /// The segmentation without the candidate.
+    pub before: Vec<String>,
+
+    /// The segmentation with the candidate at the estimated cost.
+    pub after: Vec<String>,
+}
+
+impl ExampleReport {
+    /// Whether the estimated cost gives the expected segmentation.
+    pub fn satisfied(&self, cost: i64) -> bool {
+        (cost < self.threshold) == self.positive
+    }
+}
+
+/// The result of [`CostEstimator::estimate`](struct.CostEstimator.html#method.estimate).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CostEstimate {
+    /// The highest cost with which the candidate is chosen in all positive examples, raised
+    /// to avoid being chosen in negative ones if possible. `None` if no occurrence is found.
+    pub cost: Option<i16>,
+
+    /// Whether the cost satisfies all the examples.
+    pub consistent: bool,
+
+    /// Entries with the same surface in the dictionary, found with
+    /// [`Model::lookup`][compat::Model::lookup].
+    ///
+    /// [compat::Model::lookup]: ../compat/struct.Model.html#method.lookup
+    pub existing: Vec<ExistingEntry>,
+
+    pub reports: Vec<ExampleReport>,
+}
+
+/// A node on the best path, positioned in the sentence.
+#[derive(Debug, Clone)]
+struct PathNode {
+    surface: String,
+    start: usize,
+    end: usize,
+    left: u16,
+    right: u16,
+    wcost: i64,
+    cost: i64,
+}
+
+/// Estimates word costs of candidate entries with a model.
+#[derive(Debug)]
+pub struct CostEstimator<'model> {
+    model: &'model Model,
+    tagger: Tagger<'model>,
+    lattice: Lattice,
+    lsize: u32,
+    rsize: u32,
+}
+
+impl<'model> CostEstimator<'model> {
+    pub fn new(model: &'model Model) -> Result<Self, MecabError> {
+        let mut info = model.dictionary_info();
+        while let Some(i) = info {
+            if i.type_() == DictionaryType::MECAB_SYS_DIC {
+                break;
+            }
+            info = i.next();
+        }
+        let info = info.or_else(|| model.dictionary_info());
+        Ok(CostEstimator {
+            model,
+            tagger: model.createTagger()?,
+            lattice: model.createLattice()?,
+            lsize: info.map_or(0, |info| info.lsize()),
+            rsize: info.map_or(0, |info| info.rsize()),
+        })
+    }
+
+    /// Estimates the cost of `candidate` from `positives`, the sentences where each
+    /// occurrence of the surface should be a token, and `negatives`, where it should not.
+    ///
+    /// Fails with [`EstimateError::InvalidCandidate`](enum.EstimateError.html) if the surface
+    /// is empty, or if a context ID is out of the range of the system dictionary.
+    pub fn estimate(
+        &mut self,
+        candidate: &Candidate,
+        positives: &[&str],
+        negatives: &[&str],
+    ) -> Result<CostEstimate, EstimateError> {
+        self.validate(candidate)?;
+        let mut reports = Vec::new();
+        let examples = positives
+            .iter()
+            .map(|&text| (text, true))
+            .chain(negatives.iter().map(|&text| (text, false)));
+        for (text, positive) in examples {
+            for (offset, _) in text.match_indices(candidate.surface.as_str()) {
+                reports.push(self.examine(candidate, text, offset, positive)?);
+            }
+        }
+
+        let upper = reports
+            .iter()
+            .filter(|report| report.positive)
+            .map(|report| report.threshold - 1)
+            .min();
+        let lower = reports
+            .iter()
+            .filter(|report| !report.positive)
+            .map(|report| report.threshold)
+            .max();
+        let cost = match (upper, lower) {
+            (Some(upper), _) => Some(upper),
+            (None, Some(lower)) => Some(lower),
+            (None, None) => None,
+        }
+        .map(|cost| cost.clamp(i64::from(i16::MIN), i64::from(i16::MAX)));
+        let consistent = cost.is_some_and(|cost| reports.iter().all(|r| r.satisfied(cost)));
+
+        if let Some(cost) = cost {
+            for report in &mut reports {
+                if cost >= report.threshold {
+                    report.after = report.before.clone();
+                }
+            }
+        }
+        Ok(CostEstimate {
+            cost: cost.map(|cost| cost as i16),
+            consistent,
+            existing: self.lookup(&candidate.surface)?,
+            reports,
+        })
+    }
+
+    /// Checks the candidate, since MeCab looks up transition costs without bounds checks.
+    fn validate(&self, candidate: &Candidate) -> Result<(), EstimateError> {
+        let invalid = |reason: String| Err(EstimateError::InvalidCandidate(reason));
+        if candidate.surface.is_empty() {
+            return invalid("the surface is empty".to_string());
+        }
+        if u32::from(candidate.left) >= self.lsize {
+            return invalid(format!(
+                "left ID {} >= lsize {}",
+                candidate.left, self.lsize
+            ));
+        }
+        if u32::from(candidate.right) >= self.rsize {
+            return invalid(format!(
+                "right ID {} >= rsize {}",
+                candidate.right, self.rsize
+            ));
+        }
+        Ok(())
+    }
+
+    /// Computes the threshold cost at an occurrence. `after` is the segmentation assuming
+    /// the candidate is chosen.
+    fn examine(
+        &mut self,
+        candidate: &Candidate,
+        text: &str,
+        offset: usize,
+        positive: bool,
+    ) -> Result<ExampleReport, MecabError> {
+        let end = offset + candidate.surface.len();
+        let best = self.best_path(text, None)?;
+        let constrained = self.best_path(text, Some((offset, end)))?;
+
+        // `left` ends at the occurrence, and `right` follows it.
+        let right = constrained
+            .iter()
+            .position(|node| node.start >= end)
+            .unwrap_or(constrained.len() - 1);
+        let left = constrained
+            .iter()
+            .rposition(|node| node.end <= offset)
+            .unwrap_or(0);
+        let (l, r) = (&constrained[left], &constrained[right]);
+        let best_cost = best.last().map_or(0, |eos| eos.cost);
+        let constrained_cost = constrained.last().map_or(0, |eos| eos.cost);
+        let base = constrained_cost - (r.cost - l.cost)
+            + self.transition_cost(l.right, candidate.left)
+            + self.transition_cost(candidate.right, r.left)
+            + r.wcost;
+
+        let after = constrained[1..left + 1]
+            .iter()
+            .map(|node| node.surface.clone())
+            .chain(Some(candidate.surface.clone()))
+            .chain(
+                constrained[right..constrained.len() - 1]
+                    .iter()
+                    .map(|node| node.surface.clone()),
+            )
+            .collect();
+        Ok(ExampleReport {
+            text: text.to_string(),
+            offset,
+            positive,
+            threshold: best_cost - base,
+            before: surfaces(&best),
+            after,
+        })
+    }
+
+    fn transition_cost(&self, right: u16, left: u16) -> i64 {
+        i64::from(self.model.transition_cost(right, left))
+    }
+
+    /// Parses `text`, optionally forcing token boundaries at `span`, and returns the best path
+    /// including BOS and EOS.
+    fn best_path(
+        &mut self,
+        text: &str,
+        span: Option<(usize, usize)>,
+    ) -> Result<Vec<PathNode>, MecabError> {
+        self.lattice.set_sentence(text);
+        if let Some((start, end)) = span {
+            let boundary = BoundaryConstraintType::MECAB_TOKEN_BOUNDARY;
+            self.lattice.set_boundary_constraint(start, boundary);
+            self.lattice.set_boundary_constraint(end, boundary);
+        }
+        self.tagger.parse_lattice(&mut self.lattice)?;
+
+        let mut path = Vec::new();
+        let mut pos = 0;
+        let mut node = self.lattice.bos_node();
+        while let Some(n) = node {
+            let start = pos + n.rlength() as usize - n.length() as usize;
+            pos = start + n.length() as usize;
+            let surface = if n.stat() == MECAB_BOS_NODE || n.stat() == MECAB_EOS_NODE {
+                String::new()
+            } else {
+                n.surface().unwrap_or("").to_string()
+            };
+            path.push(PathNode {
+                surface,
+                start,
+                end: pos,
+                left: n.lcAttr(),
+                right: n.rcAttr(),
+                wcost: n.wcost() as i64,
+                cost: n.cost(),
+            });
+            node = n.next();
+        }
+        Ok(path)
+    }
+
+    /// Finds the dictionary entries whose surface is exactly `surface`.
+    fn lookup(&self, surface: &str) -> Result<Vec<ExistingEntry>, MecabError> {
+        let begin = CString::new(surface)?;
+        let bytes = begin.as_bytes_with_nul();
+        let end = CStr::from_bytes_with_nul(&bytes[surface.len()..]).unwrap();
+        let lattice = self.model.createLattice()?;
+        let mut entries = Vec::new();
+        let mut node = self.model.lookup(&begin, end, &lattice);
+        while let Some(n) = node {
+            if n.stat() == MECAB_NOR_NODE && n.length() as usize == surface.len() {
+                entries.push(ExistingEntry {
+                    feature: n.feature().unwrap_or("").to_string(),
+                    left: n.lcAttr(),
+                    right: n.rcAttr(),
+                    cost: n.wcost() as i64,
+                });
+            }
+            node = n.bnext();
+        }
+        Ok(entries)
+    }
+}
+
+/// Return the surfaces of a path, excluding BOS and EOS.
+fn surfaces(path: &[PathNode]) -> Vec<String> {
+    path[1..path.len() - 1]
+        .iter()
+        .map(|node| node.surface.clone())
+        .collect()
+}
@@ -0,0 +1,536 @@
+//! Evaluation of segmentation and tagging
+//!
+//! [`Evaluator`](struct.Evaluator.html) compares the output of a
+//! [`Tokenizer`][tokenizer::Tokenizer] with gold-standard annotated sentences, like
+//! `mecab-system-eval`, and returns the metrics as a struct instead of printing them.
+//!
+//! Gold-standard data can be read with [`CorpusReader`][corpus::CorpusReader].
+//!
+//! [corpus::CorpusReader]: ../corpus/struct.CorpusReader.html
+//! [tokenizer::Tokenizer]: ../tokenizer/struct.Tokenizer.html
+
+use compat::MecabError;
+use corpus::Morpheme;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use tokenizer::{split_features, Tokenizer};
+
+/// The part of the features compared at a level of evaluation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Level {
+    /// The first `n` fields, e.g., `Fields(1)` for the major part of speech.
+    Fields(usize),
+
+    /// The whole feature string.
+    All,
+}
+
+impl Level {
+    fn matches(self, gold: &str, system: &str) -> bool {
+        match self {
+            Level::Fields(n) => {
+                let gold = split_features(gold);
+                let system = split_features(system);
+                gold[..n.min(gold.len())] == system[..n.min(system.len())]
+            }
+            Level::All => gold == system,
+        }
+    }
+}
+
+/// Counts of correct, gold and system tokens.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Score {
+    /// The number of system tokens which agree with gold tokens.
+    pub correct: usize,
+
+    /// The number of gold tokens.
+    pub gold: usize,
+
+    /// The number of system tokens.
+    pub system: usize,
+}
+
+impl Score {
+    pub fn precision(&self) -> f64 {
+        ratio(self.correct, self.system)
+    }
+
+    pub fn recall(&self) -> f64 {
+        ratio(self.correct, self.gold)
+    }
+
+    pub fn f1(&self) -> f64 {
+        let (p, r) = (self.precision(), self.recall());
+        if p + r == 0.0 {
+            0.0
+        } else {
+            2.0 * p * r / (p + r)
+        }
+    }
+}
+
+/// Scores at a level of evaluation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LevelScore {
+    pub level: Level,
+
+    /// Tokens are correct if their boundaries and features at the level agree.
+    pub score: Score,
+
+    /// The number of tokens whose boundaries agree, for which features are compared.
+    pub compared: usize,
+}
+
+impl LevelScore {
+    /// The ratio of tokens with the correct features among those with the correct
+    /// boundaries.
+    pub fn accuracy(&self) -> f64 {
+        ratio(self.score.correct, self.compared)
+    }
+}
+
+/// The kind of a [`Confusion`](struct.Confusion.html).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ConfusionKind {
+    /// The text is segmented differently. `gold` and `system` are the surfaces separated by
+    /// spaces.
+    Boundary,
+
+    /// The boundaries agree, but the parts of speech differ. `gold` and `system` are the
+    /// parts of speech.
+    Pos,
+}
+
+/// A mismatch between gold and system tokens, with the number of its occurrences.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Confusion {
+    pub kind: ConfusionKind,
+    pub gold: String,
+    pub system: String,
+    pub count: usize,
+}
+
+/// The result of evaluation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Evaluation {
+    /// The number of sentences.
+    pub sentences: usize,
+
+    /// The scores of token boundaries.
+    pub boundary: Score,
+
+    /// The scores at each configured level.
+    pub levels: Vec<LevelScore>,
+
+    /// The most frequent mismatches, in the descending order of occurrences.
+    pub confusions: Vec<Confusion>,
+}
+
+/// Evaluates segmentation and tagging against gold-standard data.
+///
+/// ```rust,no_run
+/// use metalcab::compat::Tagger;
+/// use metalcab::corpus::Morpheme;
+/// use metalcab::eval::Evaluator;
+/// use metalcab::tokenizer::Tokenizer;
+/// use std::ffi::CStr;
+///
+/// let tagger = Tagger::create2(CStr::from_bytes_with_nul(b"\0").unwrap()).unwrap();
+/// let mut tokenizer = Tokenizer::new(tagger);
+/// let gold = vec![vec![
+///     Morpheme::new("太郎", "名詞,固有名詞,人名,名,*,*,太郎,タロウ,タロー"),
+///     Morpheme::new("が", "助詞,格助詞,一般,*,*,*,が,ガ,ガ"),
+///     Morpheme::new("来る", "動詞,自立,*,*,カ変・来ル,基本形,来る,クル,クル"),
+/// ]];
+/// let evaluation = Evaluator::new().evaluate(&mut tokenizer, &gold).unwrap();
+/// assert!(evaluation.boundary.f1() > 0.9);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Evaluator {
+    levels: Vec<Level>,
+    pos_fields: usize,
+    max_confusions: usize,
+}
+
+impl Evaluator {
+    /// Creates an evaluator with the levels of the first 1 to 4 fields and the whole
+    /// features, comparing parts of speech in the first 4 fields and listing 20 confusions.
+    pub fn new() -> Self {
+        Evaluator {
+            levels: vec![
+                Level::Fields(1),
+                Level::Fields(2),
+                Level::Fields(3),
+                Level::Fields(4),
+                Level::All,
+            ],
+            pos_fields: 4,
+            max_confusions: 20,
+        }
+    }
+
+    /// Sets the levels at which features are compared.
+    pub fn levels(mut self, levels: &[Level]) -> Self {
+        self.levels = levels.to_vec();
+        self
+    }
+
+    /// Sets the number of features forming the part of speech in confusions, e.g., `4` for
+    /// the IPA dictionary and `6` for UniDic.
+    pub fn pos_fields(mut self, pos_fields: usize) -> Self {
+        self.pos_fields = pos_fields;
+        self
+    }
+
+    /// Sets the maximum number of confusions listed.
+    pub fn max_confusions(mut self, max_confusions: usize) -> Self {
+        self.max_confusions = max_confusions;
+        self
+    }
+
+    /// Tokenizes the text of each gold sentence and compares the result with it.
+    pub fn evaluate(
+        &self,
+        tokenizer: &mut Tokenizer,
+        gold: &[Vec<Morpheme>],
+    ) -> Result<Evaluation, MecabError> {
+        let mut system = Vec::with_capacity(gold.len());
+        for sentence in gold {
+            let text: String = sentence.iter().map(|m| m.surface.as_str()).collect();
+            let tokens = tokenizer.tokenize(&text)?;
+            system.push(tokens.iter().map(Morpheme::from).collect::<Vec<_>>());
+        }
+        Ok(self.compare(gold, &system))
+    }
+
+    /// Compares pairs of gold and system sentences. The sentences in a pair should have the
+    /// same text, except for whitespace.
+    pub fn compare(&self, gold: &[Vec<Morpheme>], system: &[Vec<Morpheme>]) -> Evaluation {
+        let mut boundary = Score::default();
+        let mut levels: Vec<LevelScore> = self
+            .levels
+            .iter()
+            .map(|&level| LevelScore {
+                level,
+                score: Score::default(),
+                compared: 0,
+            })
+            .collect();
+        let mut confusions = HashMap::new();
+        for (gold, system) in gold.iter().zip(system) {
+            boundary.gold += gold.len();
+            boundary.system += system.len();
+            for region in align(gold, system) {
+                match region {
+                    Region::Match(g, s) => {
+                        boundary.correct += 1;
+                        for level in &mut levels {
+                            level.compared += 1;
+                            if level.level.matches(&g.feature, &s.feature) {
+                                level.score.correct += 1;
+                            }
+                        }
+                        let pos = Level::Fields(self.pos_fields);
+                        if !pos.matches(&g.feature, &s.feature) {
+                            let key = (
+                                ConfusionKind::Pos,
+                                self.pos(&g.feature),
+                                self.pos(&s.feature),
+                            );
+                            *confusions.entry(key).or_insert(0) += 1;
+                        }
+                    }
+                    Region::Mismatch(g, s) => {
+                        let key = (ConfusionKind::Boundary, surfaces(g), surfaces(s));
+                        *confusions.entry(key).or_insert(0) += 1;
+                    }
+                }
+            }
+        }
+        for level in &mut levels {
+            level.score.gold = boundary.gold;
+            level.score.system = boundary.system;
+        }
+
+        let mut confusions: Vec<Confusion> = confusions
+            .into_iter()
+            .map(|((kind, gold, system), count)| Confusion {
+                kind,
+                gold,
+                system,
+                count,
+            })
+            .collect();
+        confusions.sort_by(|a, b| match b.count.cmp(&a.count) {
+            Ordering::Equal => (&a.gold, &a.system).cmp(&(&b.gold, &b.system)),
+            ord => ord,
+        });
+        confusions.truncate(self.max_confusions);
+        Evaluation {
+            sentences: gold.len().min(system.len()),
+            boundary,
+            levels,
+            confusions,
+        }
+    }
+
+    fn pos(&self, feature: &str) -> String {
+        let fields = split_features(feature);
+        fields[..self.pos_fields.min(fields.len())].join(",")
+    }
+}
+
+impl Default for Evaluator {
+    fn default() -> Self {
+        Evaluator::new()
+    }
+}
+
+/// A pair of tokens with the same boundaries, or minimal runs of tokens covering the same
+/// span but segmented differently.
+enum Region<'a> {
+    Match(&'a Morpheme, &'a Morpheme),
+    Mismatch(&'a [Morpheme], &'a [Morpheme]),
+}
+
+/// Aligns gold and system tokens by their character spans in the text, ignoring whitespace.
+fn align<'a>(gold: &'a [Morpheme], system: &'a [Morpheme]) -> Vec<Region<'a>> {
+    let gold_spans = spans(gold);
+    let system_spans = spans(system);
+    let mut regions = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < gold.len() || j < system.len() {
+        if i < gold.len() && j < system.len() && gold_spans[i] == system_spans[j] {
+            regions.push(Region::Match(&gold[i], &system[j]));
+            i += 1;
+            j += 1;
+            continue;
+        }
+        // Extend the region until the spans are in sync again.
+        let (i0, j0) = (i, j);
+        let mut end = 0;
+        if i < gold.len() {
+            end = end.max(gold_spans[i].1);
+            i += 1;
+        }
+        if j < system.len() {
+            end = end.max(system_spans[j].1);
+            j += 1;
+        }
+        loop {
+            let (i1, j1) = (i, j);
+            while i < gold.len() && gold_spans[i].0 < end {
+                end = end.max(gold_spans[i].1);
+                i += 1;
+            }
+            while j < system.len() && system_spans[j].0 < end {
+                end = end.max(system_spans[j].1);
+                j += 1;
+            }
+            if (i, j) == (i1, j1) {
+                break;
+            }
+        }
+        regions.push(Region::Mismatch(&gold[i0..i], &system[j0..j]));
+    }
+    regions
+}
+
+/// Return the spans of the tokens in the text with whitespace removed.
+fn spans(morphemes: &[Morpheme]) -> Vec<(usize, usize)> {
+    let mut pos = 0;
+    morphemes
+        .iter()
+        .map(|m| {
+            let start = pos;
+            pos += m.surface.chars().filter(|c| !c.is_whitespace()).count();
+            (start, pos)
+        })
+        .collect()
+}
+
+fn surfaces(morphemes: &[Morpheme]) -> String {
+    morphemes
+        .iter()
+        .map(|m| m.surface.as_str())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn ratio(numerator: usize, denominator: usize) -> f64 {
+    if denominator == 0 {
+        0.0
+    } else {
+        numerator as f64 / denominator as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOKYO: &str = "名詞,固有名詞,地域,一般,*,*,東京,トウキョウ,トーキョー";
+    const TO: &str = "名詞,接尾,地域,*,*,*,都,ト,ト";
+    const TOKYO_TO: &str = "名詞,固有名詞,地域,一般,*,*,東京都,トウキョウト,トーキョート";
+    const NI: &str = "助詞,格助詞,一般,*,*,*,に,ニ,ニ";
+    const IKU: &str = "動詞,自立,*,*,五段・カ行促音便,基本形,行く,イク,イク";
+
+    fn sentence(morphemes: &[(&str, &str)]) -> Vec<Morpheme> {
+        morphemes
+            .iter()
+            .map(|&(surface, feature)| Morpheme::new(surface, feature))
+            .collect()
+    }
+
+    fn confusion(kind: ConfusionKind, gold: &str, system: &str, count: usize) -> Confusion {
+        Confusion {
+            kind,
+            gold: gold.to_string(),
+            system: system.to_string(),
+            count,
+        }
+    }
+
+    #[test]
+    fn test_identical() {
+        let gold = vec![sentence(&[("東京都", TOKYO_TO), ("に", NI), ("行く", IKU)])];
+        let evaluation = Evaluator::new().compare(&gold, &gold);
+        assert_eq!(evaluation.sentences, 1);
+        let score = Score {
+            correct: 3,
+            gold: 3,
+            system: 3,
+        };
+        assert_eq!(evaluation.boundary, score);
+        assert_eq!(evaluation.levels.len(), 5);
+        for level in &evaluation.levels {
+            assert_eq!(level.score, score);
+            assert_eq!(level.compared, 3);
+            assert_eq!(level.accuracy(), 1.0);
+        }
+        assert_eq!(evaluation.boundary.f1(), 1.0);
+        assert!(evaluation.confusions.is_empty());
+    }
+
+    #[test]
+    fn test_over_segmented() {
+        let gold = vec![sentence(&[("東京都", TOKYO_TO), ("に", NI)])];
+        let system = vec![sentence(&[("東京", TOKYO), ("都", TO), ("に", NI)])];
+        let evaluation = Evaluator::new().compare(&gold, &system);
+        assert_eq!(
+            evaluation.boundary,
+            Score {
+                correct: 1,
+                gold: 2,
+                system: 3,
+            }
+        );
+        assert_eq!(evaluation.boundary.precision(), 1.0 / 3.0);
+        assert_eq!(evaluation.boundary.recall(), 0.5);
+        assert_eq!(evaluation.boundary.f1(), 0.4);
+        assert_eq!(evaluation.levels[0].compared, 1);
+        assert_eq!(
+            evaluation.confusions,
+            [confusion(ConfusionKind::Boundary, "東京都", "東京 都", 1)]
+        );
+    }
+
+    #[test]
+    fn test_under_segmented() {
+        let gold = vec![sentence(&[("に", NI), ("東京", TOKYO), ("都", TO)])];
+        let system = vec![sentence(&[("に", NI), ("東京都", TOKYO_TO)])];
+        let evaluation = Evaluator::new().compare(&gold, &system);
+        assert_eq!(
+            evaluation.boundary,
+            Score {
+                correct: 1,
+                gold: 3,
+                system: 2,
+            }
+        );
+        assert_eq!(
+            evaluation.confusions,
+            [confusion(ConfusionKind::Boundary, "東京 都", "東京都", 1)]
+        );
+    }
+
+    #[test]
+    fn test_different_pos() {
+        let gold = vec![sentence(&[("東京", TOKYO), ("に", NI)])];
+        let system = vec![sentence(&[("東京", TO), ("に", NI)])];
+        let evaluation = Evaluator::new()
+            .levels(&[Level::Fields(1), Level::Fields(2), Level::All])
+            .compare(&gold, &system);
+        assert_eq!(evaluation.boundary.correct, 2);
+        let correct: Vec<_> = evaluation
+            .levels
+            .iter()
+            .map(|level| level.score.correct)
+            .collect();
+        assert_eq!(correct, [2, 1, 1]);
+        assert_eq!(evaluation.levels[1].accuracy(), 0.5);
+        assert_eq!(
+            evaluation.confusions,
+            [confusion(
+                ConfusionKind::Pos,
+                "名詞,固有名詞,地域,一般",
+                "名詞,接尾,地域,*",
+                1
+            )]
+        );
+    }
+
+    #[test]
+    fn test_max_confusions() {
+        let gold = vec![
+            sentence(&[("東京", TOKYO), ("に", NI)]),
+            sentence(&[("東京", TOKYO), ("行く", IKU)]),
+            sentence(&[("東京都", TOKYO_TO)]),
+        ];
+        let system = vec![
+            sentence(&[("東京", TO), ("に", NI)]),
+            sentence(&[("東京", TO), ("行く", NI)]),
+            sentence(&[("東京", TOKYO), ("都", TO)]),
+        ];
+        let evaluation = Evaluator::new()
+            .pos_fields(1)
+            .max_confusions(2)
+            .compare(&gold, &system);
+        assert_eq!(evaluation.sentences, 3);
+        assert_eq!(
+            evaluation.confusions,
+            [
+                confusion(ConfusionKind::Pos, "動詞", "助詞", 1),
+                confusion(ConfusionKind::Boundary, "東京都", "東京 都", 1),
+            ]
+        );
+        let evaluation = Evaluator::new().max_confusions(1).compare(&gold, &system);
+        assert_eq!(
+            evaluation.confusions,
+            [confusion(
+                ConfusionKind::Pos,
+                "名詞,固有名詞,地域,一般",
+                "名詞,接尾,地域,*",
+                2
+            )]
+        );
+    }
+
+    #[test]
+    fn test_zero_denominators() {
+        let score = Score::default();
+        assert_eq!(score.precision(), 0.0);
+        assert_eq!(score.recall(), 0.0);
+        assert_eq!(score.f1(), 0.0);
+        let score = Score {
+            correct: 0,
+            gold: 2,
+            system: 3,
+        };
+        assert_eq!(score.f1(), 0.0);
+        let evaluation = Evaluator::new().compare(&[], &[]);
+        assert_eq!(evaluation.sentences, 0);
+        assert_eq!(evaluation.levels[0].accuracy(), 0.0);
+    }
+}
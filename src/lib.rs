@@ -5,6 +5,7 @@
 //! - [`Model::createTagger`][compat::Model::createTagger] generates a lifetime-bounded tagger, keeping it from being orphan.
 //! - [`Model::swap`][compat::Model::swap] is marked unsafe because of several implied race conditions.
 //! - [`Tagger`][compat::Tagger], [`Model`][compat::Model], and [`Lattice`][compat::Lattice] are explicitly marked `Send + Sync`.
+//! - Errors from creating models and taggers are captured under a lock, so that concurrent failures are not reported to the wrong thread.
 //! - It exposes the raw FFI declarations for more advanced usages.
 //! - I'm planning to provide more Rusty, higher-level wrappers for these C++-compatible APIs.
 //!
@@ -51,5 +52,43 @@
 //! }
 //! ```
 
+#[cfg(unix)]
+extern crate libc;
+#[cfg(feature = "rayon")]
+extern crate rayon;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "tantivy")]
+extern crate tantivy_tokenizer_api;
+#[cfg(feature = "tokio")]
+extern crate tokio;
+#[cfg(feature = "normalize")]
+extern crate unicode_normalization;
+
 pub mod compat;
+pub mod conllu;
+pub mod corpus;
+#[cfg(unix)]
+pub mod dictionary;
+pub mod estimate;
+pub mod eval;
+#[cfg(feature = "normalize")]
+pub mod normalize;
+#[cfg(feature = "rayon")]
+pub mod parallel;
+pub mod pool;
 pub mod raw;
+pub mod reading;
+pub mod romaji;
+pub mod search;
+pub mod sentence;
+#[cfg(feature = "tokio")]
+pub mod service;
+pub mod snapshot;
+#[cfg(feature = "tantivy")]
+pub mod tantivy;
+pub mod tokenizer;
+#[cfg(unix)]
+pub mod tools;
+#[cfg(unix)]
+pub mod train;
@@ -0,0 +1,455 @@
+//! Text normalization before analysis
+//!
+//! [`Normalizer`](struct.Normalizer.html) folds full-width and half-width characters,
+//! whitespace and variants of symbols, as recommended for
+//! [mecab-ipadic-NEologd](https://github.com/neologd/mecab-ipadic-neologd/wiki/Regexp.ja),
+//! and keeps track of where each normalized character came from, so that token spans can
+//! be projected back onto the input. This module is available with the `normalize`
+//! feature.
+
+use compat::MecabError;
+use std::ops::Range;
+use tokenizer::{Token, Tokenizer};
+use unicode_normalization::char::{canonical_combining_class, decompose_compatible};
+use unicode_normalization::UnicodeNormalization;
+
+/// How tildes and wave dashes (`~∼∾〜〰～`) are normalized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Tildes {
+    Keep,
+
+    /// Removes them, as NEologd does.
+    Remove,
+
+    /// Replaces them with a character, e.g., `〜`.
+    Unify(char),
+}
+
+/// Normalizes text before analysis.
+///
+/// The steps are run in this order, each of which can be turned off:
+///
+/// - Long vowel marks: `ｰ`, `－`, `—`, `―`, `─`, `━` and `﹣` become `ー`, and runs of
+///   `ー` are squashed into one.
+/// - Tildes, as set by [`tildes`](#method.tildes).
+/// - NFKC, e.g., `ｶﾞ` into `ガ`, `Ａ` into `A` and `㍻` into `平成`.
+/// - NEologd rules: hyphens (`‐`, `−`, ...) become `-`, `’` and `”` become `'` and `"`,
+///   zero-width characters are removed, whitespace is squashed into a space and trimmed,
+///   and spaces next to Japanese characters or symbols are removed.
+///
+/// ```rust,no_run
+/// use metalcab::compat::Tagger;
+/// use metalcab::normalize::Normalizer;
+/// use metalcab::tokenizer::Tokenizer;
+/// use std::ffi::CStr;
+///
+/// let tagger = Tagger::create2(CStr::from_bytes_with_nul(b"\0").unwrap()).unwrap();
+/// let mut tokenizer = Tokenizer::new(tagger);
+/// let text = "ﾒｰｰﾙ　を 送信～！";
+/// let normalizer = Normalizer::new();
+/// // メールを送信!
+/// println!("{}", normalizer.normalize(text).as_str());
+/// for token in normalizer.tokenize(&mut tokenizer, text).unwrap() {
+///     // メール   ﾒｰｰﾙ
+///     println!("{}\t{}", token.surface, &text[token.start..token.end]);
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Normalizer {
+    nfkc: bool,
+    neologd: bool,
+    long_vowels: bool,
+    tildes: Tildes,
+}
+
+impl Default for Normalizer {
+    fn default() -> Self {
+        Normalizer::new()
+    }
+}
+
+impl Normalizer {
+    /// Creates a normalizer running all the steps, removing tildes.
+    pub fn new() -> Self {
+        Normalizer {
+            nfkc: true,
+            neologd: true,
+            long_vowels: true,
+            tildes: Tildes::Remove,
+        }
+    }
+
+    /// Creates a normalizer running only NFKC.
+    pub fn nfkc_only() -> Self {
+        Normalizer {
+            nfkc: true,
+            neologd: false,
+            long_vowels: false,
+            tildes: Tildes::Keep,
+        }
+    }
+
+    pub fn nfkc(mut self, nfkc: bool) -> Self {
+        self.nfkc = nfkc;
+        self
+    }
+
+    /// Sets whether to apply the NEologd rules for hyphens, quotes and whitespace.
+    pub fn neologd(mut self, neologd: bool) -> Self {
+        self.neologd = neologd;
+        self
+    }
+
+    /// Sets whether to unify and squash long vowel marks.
+    pub fn long_vowels(mut self, long_vowels: bool) -> Self {
+        self.long_vowels = long_vowels;
+        self
+    }
+
+    pub fn tildes(mut self, tildes: Tildes) -> Self {
+        self.tildes = tildes;
+        self
+    }
+
+    /// Normalizes `text`.
+    pub fn normalize(&self, text: &str) -> Normalized {
+        let mut units = Vec::with_capacity(text.len());
+        for (start, cluster) in clusters(text) {
+            let end = start + cluster.len();
+            let unit = |c| Unit { c, start, end };
+            let mut chars = cluster.chars();
+            let first = chars.next().unwrap();
+            if chars.next().is_none() {
+                if self.long_vowels && is_long_vowel(first) {
+                    units.push(unit('ー'));
+                    continue;
+                }
+                if is_tilde(first) {
+                    match self.tildes {
+                        Tildes::Keep => {}
+                        Tildes::Remove => continue,
+                        Tildes::Unify(c) => {
+                            units.push(unit(c));
+                            continue;
+                        }
+                    }
+                }
+            }
+            if self.nfkc {
+                units.extend(cluster.nfkc().map(unit));
+            } else {
+                units.extend(cluster.char_indices().map(|(i, c)| Unit {
+                    c,
+                    start: start + i,
+                    end: start + i + c.len_utf8(),
+                }));
+            }
+        }
+
+        if self.long_vowels {
+            units = squash(units, |c| c == 'ー', 'ー');
+        }
+        if self.neologd {
+            units.retain(|unit| !is_zero_width(unit.c));
+            for unit in &mut units {
+                unit.c = match unit.c {
+                    '’' => '\'',
+                    '”' => '"',
+                    c if c.is_whitespace() => ' ',
+                    c => c,
+                };
+            }
+            units = squash(units, is_hyphen, '-');
+            units = squash(units, |c| c == ' ', ' ');
+            units = remove_spaces(units);
+        }
+
+        let mut normalized = Normalized {
+            text: String::with_capacity(units.len()),
+            spans: Vec::with_capacity(units.len()),
+            original_len: text.len(),
+        };
+        for unit in units {
+            normalized.text.push(unit.c);
+            for _ in 0..unit.c.len_utf8() {
+                normalized.spans.push((unit.start, unit.end));
+            }
+        }
+        normalized
+    }
+
+    /// Normalizes and tokenizes `text`. Token offsets are projected onto `text`, while the
+    /// surfaces are normalized.
+    pub fn tokenize(
+        &self,
+        tokenizer: &mut Tokenizer,
+        text: &str,
+    ) -> Result<Vec<Token>, MecabError> {
+        let normalized = self.normalize(text);
+        let mut tokens = tokenizer.tokenize(normalized.as_str())?;
+        normalized.project_tokens(&mut tokens);
+        Ok(tokens)
+    }
+}
+
+/// Normalized text, with the spans in the original text of its characters.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Normalized {
+    text: String,
+    /// The original span for each byte of `text`.
+    spans: Vec<(usize, usize)>,
+    original_len: usize,
+}
+
+impl Normalized {
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    pub fn into_string(self) -> String {
+        self.text
+    }
+
+    /// Return the byte offset in the original text where the character at `offset` in the
+    /// normalized text came from.
+    pub fn original_offset(&self, offset: usize) -> usize {
+        match self.spans.get(offset) {
+            Some(&(start, _)) => start,
+            None => self.spans.last().map_or(self.original_len, |&(_, end)| end),
+        }
+    }
+
+    /// Projects a byte range of the normalized text onto the original text.
+    ///
+    /// A character made from several original characters, such as `ガ` from `ｶﾞ`, maps to
+    /// all of them, and so does a range covering only part of the characters made from one
+    /// original character, such as `平` from `㍻`.
+    pub fn original_range(&self, start: usize, end: usize) -> Range<usize> {
+        let original_start = self.original_offset(start);
+        if end <= start {
+            return original_start..original_start;
+        }
+        let original_end = match self.spans.get(end - 1) {
+            Some(&(_, end)) => end,
+            None => self.original_offset(end),
+        };
+        original_start..original_end.max(original_start)
+    }
+
+    /// Replaces the offsets of tokens of the normalized text with the original ones.
+    pub fn project_tokens(&self, tokens: &mut [Token]) {
+        for token in tokens {
+            let range = self.original_range(token.start, token.end);
+            token.start = range.start;
+            token.end = range.end;
+        }
+    }
+}
+
+/// A normalized character and its span in the original text.
+#[derive(Debug, Clone, Copy)]
+struct Unit {
+    c: char,
+    start: usize,
+    end: usize,
+}
+
+/// Splits `text` into characters followed by the characters combining with them under
+/// NFKC, such as `ﾞ` (U+FF9E), which decomposes into a combining voiced sound mark.
+fn clusters(text: &str) -> Vec<(usize, &str)> {
+    let mut clusters: Vec<(usize, &str)> = Vec::new();
+    for (i, c) in text.char_indices() {
+        let mut combining = false;
+        decompose_compatible(c, |d| {
+            if !combining {
+                combining = canonical_combining_class(d) != 0;
+            }
+        });
+        match clusters.last_mut() {
+            Some(&mut (start, ref mut cluster)) if combining => {
+                *cluster = &text[start..i + c.len_utf8()];
+            }
+            _ => clusters.push((i, &text[i..i + c.len_utf8()])),
+        }
+    }
+    clusters
+}
+
+/// Replaces each run of characters matching `pred` with `c`, spanning the whole run.
+fn squash<F: Fn(char) -> bool>(units: Vec<Unit>, pred: F, c: char) -> Vec<Unit> {
+    let mut squashed: Vec<Unit> = Vec::with_capacity(units.len());
+    // Whether the last unit is a run being squashed, rather than a `c` which was already in
+    // the text and does not match `pred`.
+    let mut in_run = false;
+    for unit in units {
+        if pred(unit.c) {
+            if in_run {
+                if let Some(last) = squashed.last_mut() {
+                    last.end = unit.end;
+                    continue;
+                }
+            }
+            squashed.push(Unit { c, ..unit });
+            in_run = true;
+        } else {
+            squashed.push(unit);
+            in_run = false;
+        }
+    }
+    squashed
+}
+
+/// Trims spaces, and removes the ones next to Japanese characters or symbols.
+fn remove_spaces(units: Vec<Unit>) -> Vec<Unit> {
+    let mut result: Vec<Unit> = Vec::with_capacity(units.len());
+    for (i, &unit) in units.iter().enumerate() {
+        if unit.c == ' ' {
+            let prev = result.last().map(|unit| unit.c);
+            let next = units.get(i + 1).map(|unit| unit.c);
+            match (prev, next) {
+                (Some(prev), Some(next)) if !is_japanese(prev) && !is_japanese(next) => {}
+                _ => continue,
+            }
+        }
+        result.push(unit);
+    }
+    result
+}
+
+fn is_long_vowel(c: char) -> bool {
+    matches!(c, 'ー' | 'ｰ' | '－' | '—' | '―' | '─' | '━' | '﹣')
+}
+
+fn is_tilde(c: char) -> bool {
+    matches!(c, '~' | '∼' | '∾' | '〜' | '〰' | '～')
+}
+
+fn is_hyphen(c: char) -> bool {
+    matches!(c, '˗' | '֊' | '‐' | '‑' | '‒' | '–' | '⁃' | '⁻' | '₋' | '−')
+}
+
+fn is_zero_width(c: char) -> bool {
+    matches!(
+        c,
+        '\u{200B}' | '\u{200C}' | '\u{200D}' | '\u{2060}' | '\u{FEFF}'
+    )
+}
+
+/// Whether spaces next to `c` are removed: CJK ideographs, kana, CJK symbols, full-width
+/// forms, and ASCII symbols, which NEologd converts into full-width ones first.
+fn is_japanese(c: char) -> bool {
+    match c {
+        '\u{4E00}'..='\u{9FFF}'
+        | '\u{3040}'..='\u{309F}'
+        | '\u{30A0}'..='\u{30FF}'
+        | '\u{3000}'..='\u{303F}'
+        | '\u{FF00}'..='\u{FFEF}' => true,
+        _ => c.is_ascii_punctuation(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(surface: &str, start: usize, end: usize) -> Token {
+        Token {
+            surface: surface.to_string(),
+            feature: String::new(),
+            start,
+            end,
+            posid: 0,
+            unknown: false,
+        }
+    }
+
+    #[test]
+    fn test_combining_characters() {
+        let text = "ｶﾞｽ";
+        let normalized = Normalizer::new().normalize(text);
+        assert_eq!(normalized.as_str(), "ガス");
+        assert_eq!(normalized.original_range(0, 3), 0..6);
+        assert_eq!(normalized.original_range(3, 6), 6..9);
+    }
+
+    #[test]
+    fn test_expanding_characters() {
+        let text = "㍻元年";
+        let normalized = Normalizer::new().normalize(text);
+        assert_eq!(normalized.as_str(), "平成元年");
+        assert_eq!(normalized.original_range(0, 3), 0..3);
+        assert_eq!(normalized.original_range(3, 6), 0..3);
+        assert_eq!(normalized.original_range(3, 9), 0..6);
+        assert_eq!(normalized.original_range(9, 12), 6..9);
+    }
+
+    #[test]
+    fn test_long_vowels() {
+        let text = "ターーーン";
+        let normalized = Normalizer::new().normalize(text);
+        assert_eq!(normalized.as_str(), "ターン");
+        assert_eq!(normalized.original_range(3, 6), 3..12);
+        assert_eq!(normalized.original_range(6, 9), 12..15);
+
+        let normalized = Normalizer::new().long_vowels(false).normalize(text);
+        assert_eq!(normalized.as_str(), text);
+    }
+
+    #[test]
+    fn test_hyphens() {
+        let text = "a-‐‐b";
+        let normalized = Normalizer::new().normalize(text);
+        assert_eq!(normalized.as_str(), "a--b");
+        assert_eq!(normalized.original_range(1, 2), 1..2);
+        assert_eq!(normalized.original_range(2, 3), 2..8);
+        assert_eq!(normalized.original_range(3, 4), 8..9);
+    }
+
+    #[test]
+    fn test_removed_characters() {
+        let text = " ～ 東京 タワー ～ ";
+        let normalized = Normalizer::new().normalize(text);
+        assert_eq!(normalized.as_str(), "東京タワー");
+        assert_eq!(normalized.original_offset(0), 5);
+        assert_eq!(normalized.original_range(0, 6), 5..11);
+        assert_eq!(normalized.original_range(6, 15), 12..21);
+        assert_eq!(normalized.original_range(15, 15), 21..21);
+
+        let normalized = Normalizer::new()
+            .tildes(Tildes::Unify('〜'))
+            .normalize(text);
+        assert_eq!(normalized.as_str(), "〜東京タワー〜");
+        assert_eq!(normalized.original_range(0, 3), 1..4);
+
+        let normalized = Normalizer::new().normalize(" \u{3000}");
+        assert_eq!(normalized.as_str(), "");
+        assert_eq!(normalized.original_range(0, 0), 4..4);
+    }
+
+    #[test]
+    fn test_spaces() {
+        let normalized = Normalizer::new().normalize("Hello \t World ！");
+        assert_eq!(normalized.as_str(), "Hello World!");
+        assert_eq!(normalized.original_range(5, 6), 5..8);
+        assert_eq!(normalized.original_range(6, 11), 8..13);
+    }
+
+    #[test]
+    fn test_project_tokens() {
+        let text = "ﾒｰｰﾙ　を 送信～！";
+        let normalized = Normalizer::new().normalize(text);
+        assert_eq!(normalized.as_str(), "メールを送信!");
+        let mut tokens = vec![
+            token("メール", 0, 9),
+            token("を", 9, 12),
+            token("送信", 12, 18),
+            token("!", 18, 19),
+        ];
+        normalized.project_tokens(&mut tokens);
+        let surfaces: Vec<_> = tokens
+            .iter()
+            .map(|token| &text[token.start..token.end])
+            .collect();
+        assert_eq!(surfaces, ["ﾒｰｰﾙ", "を", "送信", "！"]);
+    }
+}
@@ -0,0 +1,54 @@
+//! Parallel tokenization with [rayon](https://crates.io/crates/rayon)
+//!
+//! This module is available with the `rayon` feature.
+
+use compat::{Lattice, MecabError, Model, Tagger};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use rayon::{current_num_threads, current_thread_index};
+use std::sync::Mutex;
+use tokenizer::{lattice_tokens, Token};
+
+/// Tokenizes `texts` in parallel on the current rayon thread pool, returning the tokens of
+/// each text in input order. Token offsets are relative to each text.
+///
+/// Each worker thread creates a tagger and a lattice from `model` on first use and reuses
+/// them for the rest of the texts. Stops at the first error.
+///
+/// ```rust,no_run
+/// extern crate metalcab;
+/// extern crate rayon;
+///
+/// use metalcab::compat::Model;
+/// use metalcab::parallel::tokenize_par;
+/// use std::ffi::CStr;
+///
+/// # fn main() {
+/// let model = Model::create2(CStr::from_bytes_with_nul(b"\0").unwrap()).unwrap();
+/// let texts = vec!["太郎は本を読んだ。", "花子は手紙を書いた。"];
+/// let tokens = tokenize_par(&model, texts).unwrap();
+/// assert_eq!(tokens.len(), 2);
+/// # }
+/// ```
+pub fn tokenize_par<'a, I>(model: &Model, texts: I) -> Result<Vec<Vec<Token>>, MecabError>
+where
+    I: IntoParallelIterator<Item = &'a str>,
+{
+    let workers: Vec<Mutex<Option<(Tagger, Lattice)>>> = (0..current_num_threads())
+        .map(|_| Mutex::new(None))
+        .collect();
+    texts
+        .into_par_iter()
+        .map(|text| {
+            let index = current_thread_index().expect("not on a rayon worker thread");
+            // Only the worker thread itself locks its slot.
+            let mut worker = workers[index].lock().unwrap();
+            if worker.is_none() {
+                *worker = Some((model.createTagger()?, model.createLattice()?));
+            }
+            let &mut (ref tagger, ref mut lattice) = worker.as_mut().unwrap();
+            lattice.set_sentence(text);
+            tagger.parse_lattice(lattice)?;
+            Ok(lattice_tokens(lattice))
+        })
+        .collect()
+}
@@ -0,0 +1,285 @@
+//! Pool of taggers sharing a model
+//!
+//! [`TaggerPool`](struct.TaggerPool.html) keeps pairs of a tagger and a lattice created from
+//! one [`Model`][compat::Model] and lends them to threads, as a web service would do for each
+//! request.
+//!
+//! [compat::Model]: ../compat/struct.Model.html
+
+use compat::{Lattice, MecabError, Model, Node, Tagger};
+use raw::MECAB_NBEST;
+use std::mem;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use tokenizer::{lattice_tokens, Token};
+
+/// A pool of tagger and lattice pairs.
+///
+/// Pairs are created on demand, up to `max_size`, and reused after their
+/// [`PooledTagger`](struct.PooledTagger.html) guards are dropped.
+///
+/// [`swap_model`](#method.swap_model) replaces the model: idle pairs are dropped, and pairs
+/// in use are dropped when they are returned, so that new pairs are created from the new
+/// model. Pairs of the old model don't count toward `max_size`.
+///
+/// ```rust,no_run
+/// use metalcab::compat::Model;
+/// use metalcab::pool::TaggerPool;
+/// use std::ffi::CStr;
+/// use std::sync::Arc;
+///
+/// let model = Model::create2(CStr::from_bytes_with_nul(b"\0").unwrap()).unwrap();
+/// let pool = TaggerPool::new(Arc::new(model), 4);
+/// let mut tagger = pool.get().unwrap();
+/// for token in tagger.tokenize("太郎は本を読んだ。").unwrap() {
+///     println!("{}\t{}", token.surface, token.feature);
+/// }
+/// ```
+#[derive(Debug)]
+pub struct TaggerPool {
+    state: Mutex<PoolState>,
+    returned: Condvar,
+    max_size: usize,
+}
+
+#[derive(Debug)]
+struct PoolState {
+    model: Arc<Model>,
+    generation: u64,
+    idle: Vec<Entry>,
+    /// The number of pairs of the current generation, idle or in use.
+    size: usize,
+}
+
+#[derive(Debug)]
+struct Entry {
+    // Declared before `model`, so that they are dropped first.
+    tagger: Tagger<'static>,
+    lattice: Lattice,
+    model: Arc<Model>,
+    generation: u64,
+}
+
+impl Entry {
+    fn new(model: &Arc<Model>, generation: u64) -> Result<Self, MecabError> {
+        let tagger = model.createTagger()?;
+        // Safety: the tagger is dropped before `model`, which keeps the model alive, and is
+        // never lent out by a mutable reference.
+        let tagger = unsafe { mem::transmute::<Tagger, Tagger<'static>>(tagger) };
+        Ok(Entry {
+            tagger,
+            lattice: model.createLattice()?,
+            model: model.clone(),
+            generation,
+        })
+    }
+}
+
+impl TaggerPool {
+    /// Creates a pool holding at most `max_size` pairs.
+    ///
+    /// ## Panics
+    ///
+    /// Panics if `max_size` is 0.
+    pub fn new(model: Arc<Model>, max_size: usize) -> Self {
+        assert!(max_size > 0, "max_size must be positive");
+        TaggerPool {
+            state: Mutex::new(PoolState {
+                model,
+                generation: 0,
+                idle: Vec::new(),
+                size: 0,
+            }),
+            returned: Condvar::new(),
+            max_size,
+        }
+    }
+
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    /// Return the number of pairs of the current model, idle or in use.
+    pub fn size(&self) -> usize {
+        self.lock().size
+    }
+
+    /// Return the number of idle pairs.
+    pub fn idle(&self) -> usize {
+        self.lock().idle.len()
+    }
+
+    pub fn model(&self) -> Arc<Model> {
+        self.lock().model.clone()
+    }
+
+    /// Replaces the model, returning the old one. Taggers are recreated from the new model.
+    pub fn swap_model(&self, model: Arc<Model>) -> Arc<Model> {
+        let (old, idle) = {
+            let mut state = self.lock();
+            state.generation += 1;
+            state.size = 0;
+            let idle = mem::take(&mut state.idle);
+            (mem::replace(&mut state.model, model), idle)
+        };
+        // Wake up the threads waiting for the old pairs, which are no longer counted.
+        self.returned.notify_all();
+        drop(idle);
+        old
+    }
+
+    /// Borrows a pair, waiting until one is returned if there are `max_size` pairs in use.
+    pub fn get(&self) -> Result<PooledTagger<'_>, MecabError> {
+        self.acquire(None).map(|tagger| tagger.unwrap())
+    }
+
+    /// Borrows a pair, or returns `None` if there are `max_size` pairs in use.
+    pub fn try_get(&self) -> Result<Option<PooledTagger<'_>>, MecabError> {
+        self.acquire(Some(Instant::now()))
+    }
+
+    /// Borrows a pair, or returns `None` if none is returned within `timeout`.
+    pub fn get_timeout(&self, timeout: Duration) -> Result<Option<PooledTagger<'_>>, MecabError> {
+        self.acquire(Some(Instant::now() + timeout))
+    }
+
+    fn acquire(&self, deadline: Option<Instant>) -> Result<Option<PooledTagger<'_>>, MecabError> {
+        let mut state = self.lock();
+        loop {
+            if let Some(entry) = state.idle.pop() {
+                return Ok(Some(PooledTagger {
+                    pool: self,
+                    entry: Some(entry),
+                }));
+            }
+            if state.size < self.max_size {
+                break;
+            }
+            state = match deadline {
+                None => self.returned.wait(state).unwrap(),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Ok(None);
+                    }
+                    self.returned.wait_timeout(state, deadline - now).unwrap().0
+                }
+            };
+        }
+
+        // Create a new pair outside the lock.
+        state.size += 1;
+        let model = state.model.clone();
+        let generation = state.generation;
+        drop(state);
+        match Entry::new(&model, generation) {
+            Ok(entry) => Ok(Some(PooledTagger {
+                pool: self,
+                entry: Some(entry),
+            })),
+            Err(e) => {
+                let mut state = self.lock();
+                if state.generation == generation {
+                    state.size -= 1;
+                }
+                drop(state);
+                self.returned.notify_one();
+                Err(e)
+            }
+        }
+    }
+
+    fn release(&self, entry: Entry) {
+        let mut state = self.lock();
+        if entry.generation == state.generation {
+            state.idle.push(entry);
+            drop(state);
+            self.returned.notify_one();
+        } else {
+            drop(state);
+            drop(entry);
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, PoolState> {
+        self.state.lock().unwrap()
+    }
+}
+
+/// A tagger and a lattice borrowed from a [`TaggerPool`](struct.TaggerPool.html), which are
+/// returned when dropped.
+#[derive(Debug)]
+pub struct PooledTagger<'pool> {
+    pool: &'pool TaggerPool,
+    entry: Option<Entry>,
+}
+
+impl<'pool> PooledTagger<'pool> {
+    fn entry(&self) -> &Entry {
+        self.entry.as_ref().unwrap()
+    }
+
+    fn entry_mut(&mut self) -> &mut Entry {
+        self.entry.as_mut().unwrap()
+    }
+
+    /// Return the model the tagger was created from.
+    pub fn model(&self) -> &Arc<Model> {
+        &self.entry().model
+    }
+
+    pub fn tagger(&self) -> &Tagger<'_> {
+        &self.entry().tagger
+    }
+
+    pub fn lattice(&self) -> &Lattice {
+        &self.entry().lattice
+    }
+
+    pub fn lattice_mut(&mut self) -> &mut Lattice {
+        &mut self.entry_mut().lattice
+    }
+
+    /// Parses `text` into the lattice, returning the BOS node.
+    pub fn parse(&mut self, text: &str) -> Result<&Node<'_>, MecabError> {
+        let entry = self.entry_mut();
+        entry.lattice.set_sentence(text);
+        entry.tagger.parse_lattice(&mut entry.lattice)?;
+        Ok(entry.lattice.bos_node().unwrap())
+    }
+
+    /// Tokenizes `text`. Token offsets are relative to `text`.
+    pub fn tokenize(&mut self, text: &str) -> Result<Vec<Token>, MecabError> {
+        self.parse(text)?;
+        Ok(lattice_tokens(self.lattice()))
+    }
+
+    /// Tokenizes `text` into the `n` best paths, best first. Token offsets are relative to
+    /// `text`.
+    pub fn tokenize_nbest(&mut self, text: &str, n: usize) -> Result<Vec<Vec<Token>>, MecabError> {
+        let nbest = self.lattice().has_request_type(MECAB_NBEST);
+        self.lattice_mut().add_request_type(MECAB_NBEST);
+        let parsed = self.parse(text).map(|_| ());
+        let result = parsed.map(|()| {
+            let lattice = self.lattice_mut();
+            let mut paths = Vec::new();
+            // The first `next` moves to the best path, like `Tagger::nextNode`.
+            while paths.len() < n && lattice.next() {
+                paths.push(lattice_tokens(lattice));
+            }
+            paths
+        });
+        if !nbest {
+            self.lattice_mut().remove_request_type(MECAB_NBEST);
+        }
+        result
+    }
+}
+
+impl<'pool> Drop for PooledTagger<'pool> {
+    fn drop(&mut self) {
+        if let Some(entry) = self.entry.take() {
+            self.pool.release(entry);
+        }
+    }
+}
@@ -0,0 +1,486 @@
+//! Readings and furigana
+//!
+//! [`Yomi`](struct.Yomi.html) converts text into its reading with the reading field of the
+//! features, and aligns readings to the kanji in each token to make furigana, e.g.,
+//! `読み仮名` into `読(よ)み仮名(がな)`.
+
+use compat::MecabError;
+use tokenizer::{Token, Tokenizer};
+
+/// The kana used for readings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Kana {
+    Katakana,
+    Hiragana,
+}
+
+impl Kana {
+    /// Converts the kana in `s` into this kind of kana.
+    pub fn convert(self, s: &str) -> String {
+        match self {
+            Kana::Katakana => to_katakana(s),
+            Kana::Hiragana => to_hiragana(s),
+        }
+    }
+}
+
+/// Converts katakana in `s` into hiragana. Katakana without a hiragana counterpart, such as
+/// `ヷ`, and the long vowel mark `ー` are kept.
+pub fn to_hiragana(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            '\u{30A1}'..='\u{30F6}' | '\u{30FD}'..='\u{30FE}' => {
+                ::std::char::from_u32(c as u32 - 0x60).unwrap()
+            }
+            _ => c,
+        })
+        .collect()
+}
+
+/// Converts hiragana in `s` into katakana.
+pub fn to_katakana(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            '\u{3041}'..='\u{3096}' | '\u{309D}'..='\u{309E}' => {
+                ::std::char::from_u32(c as u32 + 0x60).unwrap()
+            }
+            _ => c,
+        })
+        .collect()
+}
+
+fn is_kana(c: char) -> bool {
+    match c {
+        '\u{3041}'..='\u{309F}' | '\u{30A0}'..='\u{30FF}' | '\u{31F0}'..='\u{31FF}' => c != '・',
+        _ => false,
+    }
+}
+
+/// A part of the text, annotated with its reading if it has kanji or other characters that
+/// are not read as written.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Segment {
+    pub text: String,
+    pub reading: Option<String>,
+}
+
+impl Segment {
+    fn plain(text: &str) -> Self {
+        Segment {
+            text: text.to_string(),
+            reading: None,
+        }
+    }
+}
+
+/// Extracts readings from the features of tokens.
+///
+/// ```rust,no_run
+/// use metalcab::compat::Tagger;
+/// use metalcab::reading::{self, Yomi};
+/// use metalcab::tokenizer::Tokenizer;
+/// use std::ffi::CStr;
+///
+/// let tagger = Tagger::create2(CStr::from_bytes_with_nul(b"\0").unwrap()).unwrap();
+/// let mut tokenizer = Tokenizer::new(tagger);
+/// let yomi = Yomi::ipadic();
+/// let segments = yomi.furigana(&mut tokenizer, "漢字の読み方").unwrap();
+/// // <ruby>漢字<rp>(</rp><rt>かんじ</rt><rp>)</rp></ruby>の<ruby>読<rp>(</rp>...
+/// println!("{}", reading::to_ruby_html(&segments));
+/// // 漢字(かんじ)の読(よ)み方(かた)
+/// println!("{}", reading::to_brackets(&segments, "(", ")"));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Yomi {
+    reading_field: usize,
+    kana: Kana,
+}
+
+impl Yomi {
+    /// Creates an extractor with the index of the reading field in the features, producing
+    /// hiragana.
+    pub fn new(reading_field: usize) -> Self {
+        Yomi {
+            reading_field,
+            kana: Kana::Hiragana,
+        }
+    }
+
+    /// Creates an extractor for IPADIC, whose reading field is `読み` (7).
+    pub fn ipadic() -> Self {
+        Yomi::new(7)
+    }
+
+    /// Creates an extractor for UniDic 2.x, whose reading field is `kana` (20).
+    pub fn unidic() -> Self {
+        Yomi::new(20)
+    }
+
+    /// Sets the kana of readings. Defaults to hiragana.
+    pub fn kana(mut self, kana: Kana) -> Self {
+        self.kana = kana;
+        self
+    }
+
+    /// Return the reading of a token, or `None` if the dictionary has none, e.g., for
+    /// unknown words.
+    pub fn token_reading<'a>(&self, token: &'a Token) -> Option<&'a str> {
+        token
+            .features()
+            .get(self.reading_field)
+            .cloned()
+            .filter(|&reading| !reading.is_empty() && reading != "*")
+    }
+
+    /// Converts `text` into its reading. Tokens without a reading are kept as written, and
+    /// so is the text between tokens.
+    pub fn to_reading(&self, tokenizer: &mut Tokenizer, text: &str) -> Result<String, MecabError> {
+        let tokens = tokenizer.tokenize(text)?;
+        let mut reading = String::with_capacity(text.len());
+        let mut pos = 0;
+        for token in &tokens {
+            if token.start >= pos {
+                reading.push_str(&text[pos..token.start]);
+            }
+            reading.push_str(self.token_reading(token).unwrap_or(&token.surface));
+            pos = token.end;
+        }
+        reading.push_str(&text[pos.min(text.len())..]);
+        Ok(self.kana.convert(&reading))
+    }
+
+    /// Splits `text` into segments annotated with readings.
+    ///
+    /// Within each token, the reading is aligned to the runs of kanji and other characters
+    /// by matching the kana in the surface, so that okurigana is left unannotated. If the
+    /// kana don't match the reading, the whole token is annotated.
+    pub fn furigana(
+        &self,
+        tokenizer: &mut Tokenizer,
+        text: &str,
+    ) -> Result<Vec<Segment>, MecabError> {
+        let tokens = tokenizer.tokenize(text)?;
+        Ok(self.furigana_tokens(text, &tokens))
+    }
+
+    /// Splits `text` into segments annotated with the readings of `tokens`, whose offsets are
+    /// relative to `text`.
+    fn furigana_tokens(&self, text: &str, tokens: &[Token]) -> Vec<Segment> {
+        let mut segments = Vec::new();
+        let mut pos = 0;
+        for token in tokens {
+            if token.start > pos {
+                push(&mut segments, Segment::plain(&text[pos..token.start]));
+            }
+            pos = token.end;
+            let reading = match self.token_reading(token) {
+                Some(reading) => reading,
+                None => {
+                    push(&mut segments, Segment::plain(&token.surface));
+                    continue;
+                }
+            };
+            let aligned = align(&token.surface, reading).unwrap_or_else(|| {
+                vec![Segment {
+                    text: token.surface.clone(),
+                    reading: Some(reading.to_string()),
+                }]
+            });
+            for Segment { text, reading } in aligned {
+                let reading = reading
+                    .filter(|reading| to_hiragana(reading) != to_hiragana(&text))
+                    .map(|reading| self.kana.convert(&reading));
+                push(&mut segments, Segment { text, reading });
+            }
+        }
+        if pos < text.len() {
+            push(&mut segments, Segment::plain(&text[pos..]));
+        }
+        segments
+    }
+}
+
+/// Appends a segment, merging adjacent segments without readings.
+fn push(segments: &mut Vec<Segment>, segment: Segment) {
+    if segment.text.is_empty() {
+        return;
+    }
+    if segment.reading.is_none() {
+        if let Some(last) = segments.last_mut() {
+            if last.reading.is_none() {
+                last.text.push_str(&segment.text);
+                return;
+            }
+        }
+    }
+    segments.push(segment);
+}
+
+/// Aligns `reading` to the runs of kana and other characters in `surface`.
+fn align(surface: &str, reading: &str) -> Option<Vec<Segment>> {
+    let mut runs: Vec<(String, bool)> = Vec::new();
+    for c in surface.chars() {
+        match runs.last_mut() {
+            Some(&mut (ref mut run, kana)) if kana == is_kana(c) => run.push(c),
+            _ => runs.push((c.to_string(), is_kana(c))),
+        }
+    }
+    let runs: Vec<(&str, Option<Vec<char>>)> = runs
+        .iter()
+        .map(|&(ref run, kana)| {
+            let kana = if kana {
+                Some(to_hiragana(run).chars().collect())
+            } else {
+                None
+            };
+            (run.as_str(), kana)
+        })
+        .collect();
+    let reading: Vec<char> = reading.chars().collect();
+    let hiragana: Vec<char> = to_hiragana(&reading.iter().collect::<String>())
+        .chars()
+        .collect();
+    let mut segments = Vec::new();
+    if align_runs(&runs, &reading, &hiragana, 0, &mut segments) {
+        Some(segments)
+    } else {
+        None
+    }
+}
+
+/// Matches `runs` against `reading[pos..]`, where kana runs must match exactly and other
+/// runs take at least one character.
+fn align_runs(
+    runs: &[(&str, Option<Vec<char>>)],
+    reading: &[char],
+    hiragana: &[char],
+    pos: usize,
+    segments: &mut Vec<Segment>,
+) -> bool {
+    let (&(run, ref kana), rest) = match runs.split_first() {
+        Some(first) => first,
+        None => return pos == reading.len(),
+    };
+    match *kana {
+        Some(ref kana) => {
+            if !hiragana[pos..].starts_with(kana) {
+                return false;
+            }
+            segments.push(Segment::plain(run));
+            if align_runs(rest, reading, hiragana, pos + kana.len(), segments) {
+                return true;
+            }
+            segments.pop();
+            false
+        }
+        None => {
+            for end in pos + 1..=reading.len() {
+                segments.push(Segment {
+                    text: run.to_string(),
+                    reading: Some(reading[pos..end].iter().collect()),
+                });
+                if align_runs(rest, reading, hiragana, end, segments) {
+                    return true;
+                }
+                segments.pop();
+            }
+            false
+        }
+    }
+}
+
+fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Renders segments as HTML, annotating readings with `<ruby>` elements, e.g.,
+/// `<ruby>漢字<rp>(</rp><rt>かんじ</rt><rp>)</rp></ruby>`. The text is escaped.
+pub fn to_ruby_html(segments: &[Segment]) -> String {
+    let mut html = String::new();
+    for segment in segments {
+        match segment.reading {
+            Some(ref reading) => html.push_str(&format!(
+                "<ruby>{}<rp>(</rp><rt>{}</rt><rp>)</rp></ruby>",
+                escape_html(&segment.text),
+                escape_html(reading)
+            )),
+            None => html.push_str(&escape_html(&segment.text)),
+        }
+    }
+    html
+}
+
+/// Renders segments with readings between `open` and `close` after the annotated text, e.g.,
+/// `漢字(かんじ)` with `"("` and `")"`, or `漢字《かんじ》` with `"《"` and `"》"`.
+pub fn to_brackets(segments: &[Segment], open: &str, close: &str) -> String {
+    let mut text = String::new();
+    for segment in segments {
+        text.push_str(&segment.text);
+        if let Some(ref reading) = segment.reading {
+            text.push_str(open);
+            text.push_str(reading);
+            text.push_str(close);
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(surface: &str, reading: &str, start: usize) -> Token {
+        Token {
+            surface: surface.to_string(),
+            feature: format!("名詞,一般,*,*,*,*,{},{},{}", surface, reading, reading),
+            start,
+            end: start + surface.len(),
+            posid: 0,
+            unknown: false,
+        }
+    }
+
+    fn segment(text: &str, reading: Option<&str>) -> Segment {
+        Segment {
+            text: text.to_string(),
+            reading: reading.map(|reading| reading.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_kana_conversion() {
+        assert_eq!(
+            to_hiragana("カタカナとーヴヷヽABC"),
+            "かたかなとーゔヷゝABC"
+        );
+        assert_eq!(to_katakana("ひらがなとーゔゝABC"), "ヒラガナトーヴヽABC");
+        assert_eq!(Kana::Katakana.convert("よみ"), "ヨミ");
+        assert_eq!(Kana::Hiragana.convert("ヨミ"), "よみ");
+    }
+
+    #[test]
+    fn test_align() {
+        assert_eq!(
+            align("読み方", "ヨミカタ"),
+            Some(vec![
+                segment("読", Some("ヨ")),
+                segment("み", None),
+                segment("方", Some("カタ")),
+            ])
+        );
+        assert_eq!(
+            align("お見舞い", "オミマイ"),
+            Some(vec![
+                segment("お", None),
+                segment("見舞", Some("ミマ")),
+                segment("い", None),
+            ])
+        );
+        // The kanji run takes the kana in the reading which the following kana also match.
+        assert_eq!(
+            align("気が付く", "キガツク"),
+            Some(vec![
+                segment("気", Some("キ")),
+                segment("が", None),
+                segment("付", Some("ツ")),
+                segment("く", None),
+            ])
+        );
+        assert_eq!(align("読み", "ヨム"), None);
+        assert_eq!(align("読み", "ミ"), None);
+    }
+
+    #[test]
+    fn test_furigana() {
+        let yomi = Yomi::ipadic();
+        let text = "漢字の読み方、ABC";
+        let tokens = [
+            token("漢字", "カンジ", 0),
+            token("の", "ノ", 6),
+            token("読み方", "ヨミカタ", 9),
+            token("ABC", "*", 21),
+        ];
+        let segments = yomi.furigana_tokens(text, &tokens);
+        assert_eq!(
+            segments,
+            [
+                segment("漢字", Some("かんじ")),
+                segment("の", None),
+                segment("読", Some("よ")),
+                segment("み", None),
+                segment("方", Some("かた")),
+                segment("、ABC", None),
+            ]
+        );
+        assert_eq!(
+            to_brackets(&segments, "(", ")"),
+            "漢字(かんじ)の読(よ)み方(かた)、ABC"
+        );
+
+        let segments = yomi
+            .kana(Kana::Katakana)
+            .furigana_tokens(text, &tokens[..1]);
+        assert_eq!(
+            segments,
+            [
+                segment("漢字", Some("カンジ")),
+                segment("の読み方、ABC", None)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_furigana_kana_tokens() {
+        let yomi = Yomi::ipadic();
+        let text = "ひらがなカタカナ";
+        let tokens = [
+            token("ひらがな", "ヒラガナ", 0),
+            token("カタカナ", "カタカナ", 12),
+        ];
+        assert_eq!(
+            yomi.furigana_tokens(text, &tokens),
+            [segment("ひらがなカタカナ", None)]
+        );
+    }
+
+    #[test]
+    fn test_furigana_mismatch() {
+        // The kana in the surface don't match the reading, so the whole token is annotated.
+        let yomi = Yomi::ipadic();
+        let tokens = [token("読み", "ヨム", 0)];
+        assert_eq!(
+            yomi.furigana_tokens("読み", &tokens),
+            [segment("読み", Some("よむ"))]
+        );
+        let tokens = [token("行った", "イッタ", 0)];
+        assert_eq!(
+            yomi.furigana_tokens("行った", &tokens),
+            [segment("行", Some("い")), segment("った", None)]
+        );
+    }
+
+    #[test]
+    fn test_ruby_html() {
+        let segments = [segment("<漢字>", Some("&かんじ")), segment("の\"'&", None)];
+        assert_eq!(
+            to_ruby_html(&segments),
+            "<ruby>&lt;漢字&gt;<rp>(</rp><rt>&amp;かんじ</rt><rp>)</rp></ruby>\
+             の&quot;&#39;&amp;"
+        );
+        assert_eq!(
+            to_brackets(&segments, "《", "》"),
+            "<漢字>《&かんじ》の\"'&"
+        );
+    }
+}
@@ -0,0 +1,505 @@
+//! Romanization
+//!
+//! [`Romanizer`](struct.Romanizer.html) writes analyzed text in the Latin alphabet with the
+//! Hepburn, Kunrei-shiki or Nihon-shiki system, e.g., `東京へ行きます。` as
+//! `tōkyō e iki masu.`
+
+use compat::MecabError;
+use reading::{to_hiragana, Yomi};
+use tokenizer::{Token, Tokenizer};
+
+/// A romanization system.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum System {
+    /// Modified Hepburn, e.g., `shi`, `chi`, `tsu`, `fu`, `ji`.
+    Hepburn,
+
+    /// Kunrei-shiki (ISO 3602), e.g., `si`, `ti`, `tu`, `hu`, `zi`.
+    Kunrei,
+
+    /// Nihon-shiki, which also distinguishes `di`, `du` and `wo`.
+    NihonShiki,
+}
+
+/// How long vowels are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LongVowels {
+    /// `tōkyō`
+    Macron,
+
+    /// `tôkyô`
+    Circumflex,
+
+    /// `tookyoo`
+    Repeat,
+
+    /// `tokyo`
+    Omit,
+}
+
+/// Romanizes the readings or pronunciations of tokens.
+///
+/// Long vowels are the ones marked with `ー`, as in the pronunciation field of IPADIC and
+/// UniDic (`トーキョー`). Readings spell them with kana instead (`トウキョウ`), which are
+/// romanized as written (`toukyou`).
+///
+/// - Sokuon doubles the following consonant, or becomes `t` before `ch` in Hepburn. It is
+///   dropped at the end of a word.
+/// - The syllabic n is followed by `'` before vowels and `y`, e.g., `kin'yōbi`.
+/// - The particles `は`, `へ` and `を` are written `wa`, `e` and `o`.
+///
+/// Tokens are separated by spaces, except that punctuation is attached to the neighboring
+/// words. Tokens without a reading are kept as written.
+///
+/// ```rust,no_run
+/// use metalcab::compat::Tagger;
+/// use metalcab::romaji::{Romanizer, System};
+/// use metalcab::tokenizer::Tokenizer;
+/// use std::ffi::CStr;
+///
+/// let tagger = Tagger::create2(CStr::from_bytes_with_nul(b"\0").unwrap()).unwrap();
+/// let mut tokenizer = Tokenizer::new(tagger);
+/// let romanizer = Romanizer::new(System::Hepburn);
+/// // watashi wa tōkyō e iki masu.
+/// println!("{}", romanizer.romanize(&mut tokenizer, "私は東京へ行きます。").unwrap());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Romanizer {
+    system: System,
+    long_vowels: LongVowels,
+    yomi: Yomi,
+}
+
+impl Romanizer {
+    /// Creates a romanizer for IPADIC pronunciations, writing long vowels with macrons in
+    /// Hepburn and circumflexes otherwise.
+    pub fn new(system: System) -> Self {
+        Romanizer {
+            system,
+            long_vowels: match system {
+                System::Hepburn => LongVowels::Macron,
+                System::Kunrei | System::NihonShiki => LongVowels::Circumflex,
+            },
+            yomi: Yomi::new(8),
+        }
+    }
+
+    pub fn system(&self) -> System {
+        self.system
+    }
+
+    pub fn long_vowels(mut self, long_vowels: LongVowels) -> Self {
+        self.long_vowels = long_vowels;
+        self
+    }
+
+    /// Sets the index of the features field to romanize, e.g., 8 for the pronunciation of
+    /// IPADIC (default), 7 for its reading, and 9 for the pronunciation of UniDic.
+    pub fn reading_field(mut self, field: usize) -> Self {
+        self.yomi = Yomi::new(field);
+        self
+    }
+
+    /// Tokenizes and romanizes `text`.
+    pub fn romanize(&self, tokenizer: &mut Tokenizer, text: &str) -> Result<String, MecabError> {
+        Ok(self.romanize_tokens(&tokenizer.tokenize(text)?))
+    }
+
+    /// Romanizes tokens, separating them by spaces.
+    pub fn romanize_tokens(&self, tokens: &[Token]) -> String {
+        let mut result = String::new();
+        let mut space = false;
+        for token in tokens {
+            let features = token.features();
+            let pos = features.first().cloned().unwrap_or("");
+            let pos2 = features.get(1).cloned().unwrap_or("");
+            if pos == "記号" || pos == "補助記号" {
+                let opening = pos2 == "括弧開";
+                if opening && space {
+                    result.push(' ');
+                }
+                result.push_str(punctuation(&token.surface));
+                space = !opening;
+                continue;
+            }
+            let word = match (pos, token.surface.as_str()) {
+                ("助詞", "は") => "wa".to_string(),
+                ("助詞", "へ") => "e".to_string(),
+                ("助詞", "を") => "o".to_string(),
+                _ => match self.yomi.token_reading(token) {
+                    Some(reading) => self.romanize_kana(reading),
+                    None => token.surface.clone(),
+                },
+            };
+            if word.is_empty() {
+                continue;
+            }
+            if space {
+                result.push(' ');
+            }
+            result.push_str(&word);
+            space = true;
+        }
+        result
+    }
+
+    /// Romanizes a word written in kana. Other characters are kept.
+    pub fn romanize_kana(&self, kana: &str) -> String {
+        let chars: Vec<char> = to_hiragana(kana).chars().collect();
+        let mut units = Vec::new();
+        let mut i = 0;
+        while i < chars.len() {
+            if i + 1 < chars.len() {
+                if let Some(syllable) = digraph(chars[i], chars[i + 1], self.system) {
+                    units.push(Unit::Syllable(syllable.to_string()));
+                    i += 2;
+                    continue;
+                }
+            }
+            units.push(match chars[i] {
+                'っ' => Unit::Sokuon,
+                'ー' => Unit::Long,
+                'ん' => Unit::N,
+                c => match syllable(c, self.system) {
+                    Some(syllable) => Unit::Syllable(syllable.to_string()),
+                    None => Unit::Syllable(c.to_string()),
+                },
+            });
+            i += 1;
+        }
+
+        let mut result = String::new();
+        for (i, unit) in units.iter().enumerate() {
+            let next = match units.get(i + 1) {
+                Some(Unit::Syllable(next)) => next.as_str(),
+                Some(Unit::N) => "n",
+                _ => "",
+            };
+            match *unit {
+                Unit::Syllable(ref syllable) => result.push_str(syllable),
+                Unit::Sokuon => match next.chars().next() {
+                    Some(_) if self.system == System::Hepburn && next.starts_with("ch") => {
+                        result.push('t')
+                    }
+                    Some(c) if c.is_ascii_alphabetic() && !"aiueo".contains(c) => result.push(c),
+                    _ => {}
+                },
+                Unit::N => {
+                    result.push('n');
+                    if next.starts_with(['a', 'i', 'u', 'e', 'o', 'y']) {
+                        result.push('\'');
+                    }
+                }
+                Unit::Long => {
+                    if let Some(vowel) = result.pop() {
+                        result.push_str(&long_vowel(vowel, self.long_vowels));
+                    }
+                }
+            }
+        }
+        result
+    }
+}
+
+#[derive(Debug)]
+enum Unit {
+    Syllable(String),
+    Sokuon,
+    Long,
+    N,
+}
+
+fn long_vowel(vowel: char, long_vowels: LongVowels) -> String {
+    let (macron, circumflex) = match vowel {
+        'a' => ('ā', 'â'),
+        'i' => ('ī', 'î'),
+        'u' => ('ū', 'û'),
+        'e' => ('ē', 'ê'),
+        'o' => ('ō', 'ô'),
+        // Not after a vowel.
+        _ => return vowel.to_string(),
+    };
+    match long_vowels {
+        LongVowels::Macron => macron.to_string(),
+        LongVowels::Circumflex => circumflex.to_string(),
+        LongVowels::Repeat => format!("{}{}", vowel, vowel),
+        LongVowels::Omit => vowel.to_string(),
+    }
+}
+
+/// Romanizes a syllable written with a single kana, in Hepburn, Kunrei-shiki and
+/// Nihon-shiki.
+fn syllable(c: char, system: System) -> Option<&'static str> {
+    let (hepburn, kunrei, nihon) = match c {
+        'あ' | 'ぁ' => ("a", "a", "a"),
+        'い' | 'ぃ' => ("i", "i", "i"),
+        'う' | 'ぅ' => ("u", "u", "u"),
+        'え' | 'ぇ' => ("e", "e", "e"),
+        'お' | 'ぉ' => ("o", "o", "o"),
+        'か' | 'ゕ' => ("ka", "ka", "ka"),
+        'き' => ("ki", "ki", "ki"),
+        'く' => ("ku", "ku", "ku"),
+        'け' | 'ゖ' => ("ke", "ke", "ke"),
+        'こ' => ("ko", "ko", "ko"),
+        'が' => ("ga", "ga", "ga"),
+        'ぎ' => ("gi", "gi", "gi"),
+        'ぐ' => ("gu", "gu", "gu"),
+        'げ' => ("ge", "ge", "ge"),
+        'ご' => ("go", "go", "go"),
+        'さ' => ("sa", "sa", "sa"),
+        'し' => ("shi", "si", "si"),
+        'す' => ("su", "su", "su"),
+        'せ' => ("se", "se", "se"),
+        'そ' => ("so", "so", "so"),
+        'ざ' => ("za", "za", "za"),
+        'じ' => ("ji", "zi", "zi"),
+        'ず' => ("zu", "zu", "zu"),
+        'ぜ' => ("ze", "ze", "ze"),
+        'ぞ' => ("zo", "zo", "zo"),
+        'た' => ("ta", "ta", "ta"),
+        'ち' => ("chi", "ti", "ti"),
+        'つ' => ("tsu", "tu", "tu"),
+        'て' => ("te", "te", "te"),
+        'と' => ("to", "to", "to"),
+        'だ' => ("da", "da", "da"),
+        'ぢ' => ("ji", "zi", "di"),
+        'づ' => ("zu", "zu", "du"),
+        'で' => ("de", "de", "de"),
+        'ど' => ("do", "do", "do"),
+        'な' => ("na", "na", "na"),
+        'に' => ("ni", "ni", "ni"),
+        'ぬ' => ("nu", "nu", "nu"),
+        'ね' => ("ne", "ne", "ne"),
+        'の' => ("no", "no", "no"),
+        'は' => ("ha", "ha", "ha"),
+        'ひ' => ("hi", "hi", "hi"),
+        'ふ' => ("fu", "hu", "hu"),
+        'へ' => ("he", "he", "he"),
+        'ほ' => ("ho", "ho", "ho"),
+        'ば' => ("ba", "ba", "ba"),
+        'び' => ("bi", "bi", "bi"),
+        'ぶ' => ("bu", "bu", "bu"),
+        'べ' => ("be", "be", "be"),
+        'ぼ' => ("bo", "bo", "bo"),
+        'ぱ' => ("pa", "pa", "pa"),
+        'ぴ' => ("pi", "pi", "pi"),
+        'ぷ' => ("pu", "pu", "pu"),
+        'ぺ' => ("pe", "pe", "pe"),
+        'ぽ' => ("po", "po", "po"),
+        'ま' => ("ma", "ma", "ma"),
+        'み' => ("mi", "mi", "mi"),
+        'む' => ("mu", "mu", "mu"),
+        'め' => ("me", "me", "me"),
+        'も' => ("mo", "mo", "mo"),
+        'や' | 'ゃ' => ("ya", "ya", "ya"),
+        'ゆ' | 'ゅ' => ("yu", "yu", "yu"),
+        'よ' | 'ょ' => ("yo", "yo", "yo"),
+        'ら' => ("ra", "ra", "ra"),
+        'り' => ("ri", "ri", "ri"),
+        'る' => ("ru", "ru", "ru"),
+        'れ' => ("re", "re", "re"),
+        'ろ' => ("ro", "ro", "ro"),
+        'わ' | 'ゎ' => ("wa", "wa", "wa"),
+        'ゐ' => ("i", "i", "wi"),
+        'ゑ' => ("e", "e", "we"),
+        'を' => ("o", "o", "wo"),
+        'ゔ' => ("vu", "vu", "vu"),
+        _ => return None,
+    };
+    Some(match system {
+        System::Hepburn => hepburn,
+        System::Kunrei => kunrei,
+        System::NihonShiki => nihon,
+    })
+}
+
+/// Romanizes a syllable written with a kana and a small kana, e.g., `きゃ`.
+fn digraph(c1: char, c2: char, system: System) -> Option<&'static str> {
+    let (hepburn, kunrei, nihon) = match (c1, c2) {
+        ('き', 'ゃ') => ("kya", "kya", "kya"),
+        ('き', 'ゅ') => ("kyu", "kyu", "kyu"),
+        ('き', 'ょ') => ("kyo", "kyo", "kyo"),
+        ('ぎ', 'ゃ') => ("gya", "gya", "gya"),
+        ('ぎ', 'ゅ') => ("gyu", "gyu", "gyu"),
+        ('ぎ', 'ょ') => ("gyo", "gyo", "gyo"),
+        ('し', 'ゃ') => ("sha", "sya", "sya"),
+        ('し', 'ゅ') => ("shu", "syu", "syu"),
+        ('し', 'ょ') => ("sho", "syo", "syo"),
+        ('し', 'ぇ') => ("she", "sye", "sye"),
+        ('じ', 'ゃ') => ("ja", "zya", "zya"),
+        ('じ', 'ゅ') => ("ju", "zyu", "zyu"),
+        ('じ', 'ょ') => ("jo", "zyo", "zyo"),
+        ('じ', 'ぇ') => ("je", "zye", "zye"),
+        ('ち', 'ゃ') => ("cha", "tya", "tya"),
+        ('ち', 'ゅ') => ("chu", "tyu", "tyu"),
+        ('ち', 'ょ') => ("cho", "tyo", "tyo"),
+        ('ち', 'ぇ') => ("che", "tye", "tye"),
+        ('ぢ', 'ゃ') => ("ja", "zya", "dya"),
+        ('ぢ', 'ゅ') => ("ju", "zyu", "dyu"),
+        ('ぢ', 'ょ') => ("jo", "zyo", "dyo"),
+        ('に', 'ゃ') => ("nya", "nya", "nya"),
+        ('に', 'ゅ') => ("nyu", "nyu", "nyu"),
+        ('に', 'ょ') => ("nyo", "nyo", "nyo"),
+        ('ひ', 'ゃ') => ("hya", "hya", "hya"),
+        ('ひ', 'ゅ') => ("hyu", "hyu", "hyu"),
+        ('ひ', 'ょ') => ("hyo", "hyo", "hyo"),
+        ('び', 'ゃ') => ("bya", "bya", "bya"),
+        ('び', 'ゅ') => ("byu", "byu", "byu"),
+        ('び', 'ょ') => ("byo", "byo", "byo"),
+        ('ぴ', 'ゃ') => ("pya", "pya", "pya"),
+        ('ぴ', 'ゅ') => ("pyu", "pyu", "pyu"),
+        ('ぴ', 'ょ') => ("pyo", "pyo", "pyo"),
+        ('み', 'ゃ') => ("mya", "mya", "mya"),
+        ('み', 'ゅ') => ("myu", "myu", "myu"),
+        ('み', 'ょ') => ("myo", "myo", "myo"),
+        ('り', 'ゃ') => ("rya", "rya", "rya"),
+        ('り', 'ゅ') => ("ryu", "ryu", "ryu"),
+        ('り', 'ょ') => ("ryo", "ryo", "ryo"),
+        ('く', 'ゎ') => ("kwa", "kwa", "kwa"),
+        ('ぐ', 'ゎ') => ("gwa", "gwa", "gwa"),
+        ('つ', 'ぁ') => ("tsa", "tsa", "tsa"),
+        ('つ', 'ぃ') => ("tsi", "tsi", "tsi"),
+        ('つ', 'ぇ') => ("tse", "tse", "tse"),
+        ('つ', 'ぉ') => ("tso", "tso", "tso"),
+        ('て', 'ぃ') => ("ti", "ti", "ti"),
+        ('て', 'ゅ') => ("tyu", "tyu", "tyu"),
+        ('で', 'ぃ') => ("di", "di", "di"),
+        ('で', 'ゅ') => ("dyu", "dyu", "dyu"),
+        ('と', 'ぅ') => ("tu", "tu", "tu"),
+        ('ど', 'ぅ') => ("du", "du", "du"),
+        ('ふ', 'ぁ') => ("fa", "fa", "fa"),
+        ('ふ', 'ぃ') => ("fi", "fi", "fi"),
+        ('ふ', 'ぇ') => ("fe", "fe", "fe"),
+        ('ふ', 'ぉ') => ("fo", "fo", "fo"),
+        ('ふ', 'ゅ') => ("fyu", "fyu", "fyu"),
+        ('う', 'ぃ') => ("wi", "wi", "wi"),
+        ('う', 'ぇ') => ("we", "we", "we"),
+        ('う', 'ぉ') => ("wo", "wo", "wo"),
+        ('ゔ', 'ぁ') => ("va", "va", "va"),
+        ('ゔ', 'ぃ') => ("vi", "vi", "vi"),
+        ('ゔ', 'ぇ') => ("ve", "ve", "ve"),
+        ('ゔ', 'ぉ') => ("vo", "vo", "vo"),
+        ('ゔ', 'ゅ') => ("vyu", "vyu", "vyu"),
+        _ => return None,
+    };
+    Some(match system {
+        System::Hepburn => hepburn,
+        System::Kunrei => kunrei,
+        System::NihonShiki => nihon,
+    })
+}
+
+/// Romanizes a punctuation mark, or keeps it.
+fn punctuation(surface: &str) -> &str {
+    match surface {
+        "。" | "．" => ".",
+        "、" | "，" => ",",
+        "！" => "!",
+        "？" => "?",
+        "：" => ":",
+        "；" => ";",
+        "・" => "-",
+        "「" | "」" | "『" | "』" => "\"",
+        "（" => "(",
+        "）" => ")",
+        "［" | "【" => "[",
+        "］" | "】" => "]",
+        "〜" | "～" => "~",
+        _ => surface,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_romanize_kana() {
+        let cases = [
+            ("がっこう", ["gakkou", "gakkou", "gakkou"]),
+            ("しんあい", ["shin'ai", "sin'ai", "sin'ai"]),
+            ("ちぢむ", ["chijimu", "tizimu", "tidimu"]),
+            ("とうきょう", ["toukyou", "toukyou", "toukyou"]),
+            ("おおさか", ["oosaka", "oosaka", "oosaka"]),
+            ("トーキョー", ["tōkyō", "tôkyô", "tôkyô"]),
+            ("ガッコー", ["gakkō", "gakkô", "gakkô"]),
+            ("オーサカ", ["ōsaka", "ôsaka", "ôsaka"]),
+            ("まっちゃ", ["matcha", "mattya", "mattya"]),
+            ("きんよう", ["kin'you", "kin'you", "kin'you"]),
+            ("しんぶん", ["shinbun", "sinbun", "sinbun"]),
+            ("つづく", ["tsuzuku", "tuzuku", "tuduku"]),
+            ("ぢゃ", ["ja", "zya", "dya"]),
+            ("ふぁいる", ["fairu", "fairu", "fairu"]),
+            ("あっ", ["a", "a", "a"]),
+        ];
+        let systems = [System::Hepburn, System::Kunrei, System::NihonShiki];
+        for &(kana, ref expected) in &cases {
+            for (&system, &expected) in systems.iter().zip(expected) {
+                let romanizer = Romanizer::new(system);
+                assert_eq!(
+                    romanizer.romanize_kana(kana),
+                    expected,
+                    "{} in {:?}",
+                    kana,
+                    system
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_long_vowels() {
+        let romanizer = Romanizer::new(System::Hepburn);
+        let cases = [
+            (LongVowels::Macron, "tōkyō"),
+            (LongVowels::Circumflex, "tôkyô"),
+            (LongVowels::Repeat, "tookyoo"),
+            (LongVowels::Omit, "tokyo"),
+        ];
+        for &(long_vowels, expected) in &cases {
+            let romanizer = romanizer.long_vowels(long_vowels);
+            assert_eq!(romanizer.romanize_kana("トーキョー"), expected);
+        }
+    }
+
+    fn token(surface: &str, feature: &str) -> Token {
+        Token {
+            surface: surface.to_string(),
+            feature: feature.to_string(),
+            start: 0,
+            end: 0,
+            posid: 0,
+            unknown: false,
+        }
+    }
+
+    #[test]
+    fn test_romanize_tokens() {
+        let tokens = [
+            token("私", "名詞,代名詞,一般,*,*,*,私,ワタシ,ワタシ"),
+            token("は", "助詞,係助詞,*,*,*,*,は,ハ,ワ"),
+            token("「", "記号,括弧開,*,*,*,*,「,「,「"),
+            token(
+                "東京",
+                "名詞,固有名詞,地域,一般,*,*,東京,トウキョウ,トーキョー",
+            ),
+            token("」", "記号,括弧閉,*,*,*,*,」,」,」"),
+            token("へ", "助詞,格助詞,一般,*,*,*,へ,ヘ,エ"),
+            token("本", "名詞,一般,*,*,*,*,本,ホン,ホン"),
+            token("を", "助詞,格助詞,一般,*,*,*,を,ヲ,ヲ"),
+            token("ABC", "名詞,固有名詞,組織,*,*,*,*"),
+            token("。", "記号,句点,*,*,*,*,。,。,。"),
+        ];
+        let romanizer = Romanizer::new(System::Hepburn);
+        assert_eq!(
+            romanizer.romanize_tokens(&tokens),
+            "watashi wa \"tōkyō\" e hon o ABC."
+        );
+        // The particles are decided by the part of speech, whatever the reading says.
+        let romanizer = Romanizer::new(System::NihonShiki).reading_field(7);
+        assert_eq!(
+            romanizer.romanize_tokens(&tokens),
+            "watasi wa \"toukyou\" e hon o ABC."
+        );
+    }
+}
@@ -0,0 +1,337 @@
+//! Search-mode tokenization
+//!
+//! MeCab's best path keeps long compound nouns like `関西国際空港` as a single token, so that
+//! a query for `空港` doesn't match them. [`SearchMode`](struct.SearchMode.html) emits both
+//! the compound and its parts, similarly to the search mode of Kuromoji and Lucene's
+//! `JapaneseTokenizer`.
+
+use compat::MecabError;
+use pool::PooledTagger;
+use tokenizer::{pos_matches, split_features, Token, Tokenizer};
+
+/// A token with its position in the token graph.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchToken {
+    pub token: Token,
+
+    /// The number of positions from the previous token, which is 0 for a token at the same
+    /// position as the previous one.
+    pub position_increment: usize,
+
+    /// The number of positions the token spans, which is the number of parts for a
+    /// compound.
+    pub position_length: usize,
+}
+
+/// Decompounds long compound nouns with the N-best paths.
+///
+/// For each token of the best path that matches one of the compound tags and is at least
+/// `min_length` characters long, the first of the `nbest` best paths that splits the span of
+/// the token at different boundaries gives its parts. The compound is emitted first,
+/// spanning the positions of its parts, followed by the parts. The first part has a position
+/// increment of 0 and the other parts an increment of 1, so that both whole-word, partial
+/// and phrase queries on the parts match:
+///
+/// | token | position increment | position length |
+/// |-------|--------------------|-----------------|
+/// | 関西国際空港 | 1 | 3 |
+/// | 関西 | 0 | 1 |
+/// | 国際 | 1 | 1 |
+/// | 空港 | 1 | 1 |
+///
+/// ```rust,no_run
+/// use metalcab::compat::Tagger;
+/// use metalcab::search::SearchMode;
+/// use metalcab::tokenizer::Tokenizer;
+/// use std::ffi::CStr;
+///
+/// let tagger = Tagger::create2(CStr::from_bytes_with_nul(b"\0").unwrap()).unwrap();
+/// let mut tokenizer = Tokenizer::new(tagger);
+/// for token in SearchMode::new().tokenize(&mut tokenizer, "関西国際空港に着いた。").unwrap() {
+///     println!("{} +{}", token.token.surface, token.position_increment);
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchMode {
+    compound_tags: Vec<Vec<String>>,
+    min_length: usize,
+    nbest: usize,
+}
+
+impl SearchMode {
+    /// Creates a search mode decompounding nouns (`名詞`) of at least 3 characters with the
+    /// 10 best paths.
+    pub fn new() -> Self {
+        SearchMode {
+            compound_tags: vec![vec!["名詞".to_string()]],
+            min_length: 3,
+            nbest: 10,
+        }
+    }
+
+    /// Sets the parts of speech of compounds. Each tag is a comma-separated prefix of the
+    /// feature fields, where `*` matches any field.
+    pub fn compound_tags(mut self, tags: &[&str]) -> Self {
+        self.compound_tags = tags
+            .iter()
+            .map(|tag| split_features(tag).iter().map(|f| f.to_string()).collect())
+            .collect();
+        self
+    }
+
+    /// Sets the minimum number of characters of compounds.
+    pub fn min_length(mut self, min_length: usize) -> Self {
+        self.min_length = min_length;
+        self
+    }
+
+    /// Sets the number of paths searched for parts.
+    pub fn nbest(mut self, nbest: usize) -> Self {
+        self.nbest = nbest;
+        self
+    }
+
+    fn is_compound(&self, token: &Token) -> bool {
+        let features = split_features(&token.feature);
+        token.surface.chars().count() >= self.min_length
+            && self
+                .compound_tags
+                .iter()
+                .any(|tag| pos_matches(tag, &features))
+    }
+
+    /// Tokenizes `text`. Token offsets are relative to `text`.
+    pub fn tokenize(
+        &self,
+        tokenizer: &mut Tokenizer,
+        text: &str,
+    ) -> Result<Vec<SearchToken>, MecabError> {
+        let best = tokenizer.tokenize(text)?;
+        self.decompound(best, |n| tokenizer.tokenize_nbest(text, n))
+    }
+
+    /// Tokenizes `text` with a tagger borrowed from a pool. Token offsets are relative to
+    /// `text`.
+    pub fn tokenize_pooled(
+        &self,
+        tagger: &mut PooledTagger,
+        text: &str,
+    ) -> Result<Vec<SearchToken>, MecabError> {
+        let best = tagger.tokenize(text)?;
+        self.decompound(best, |n| tagger.tokenize_nbest(text, n))
+    }
+
+    /// Splits the compounds in the best path `best` into parts found in the paths returned
+    /// by `nbest`, which is called only if there is a compound.
+    fn decompound<F>(&self, best: Vec<Token>, nbest: F) -> Result<Vec<SearchToken>, MecabError>
+    where
+        F: FnOnce(usize) -> Result<Vec<Vec<Token>>, MecabError>,
+    {
+        if !best.iter().any(|token| self.is_compound(token)) {
+            return Ok(best.into_iter().map(SearchToken::from).collect());
+        }
+        let paths = nbest(self.nbest)?;
+        let mut result = Vec::with_capacity(best.len());
+        for token in best {
+            let parts = if self.is_compound(&token) {
+                paths.iter().filter_map(|path| split(path, &token)).next()
+            } else {
+                None
+            };
+            match parts {
+                Some(parts) => {
+                    result.push(SearchToken {
+                        token,
+                        position_increment: 1,
+                        position_length: parts.len(),
+                    });
+                    for (i, part) in parts.iter().enumerate() {
+                        result.push(SearchToken {
+                            token: part.clone(),
+                            position_increment: if i == 0 { 0 } else { 1 },
+                            position_length: 1,
+                        });
+                    }
+                }
+                None => result.push(SearchToken::from(token)),
+            }
+        }
+        Ok(result)
+    }
+}
+
+impl Default for SearchMode {
+    fn default() -> Self {
+        SearchMode::new()
+    }
+}
+
+impl From<Token> for SearchToken {
+    /// A token following the previous one, spanning one position.
+    fn from(token: Token) -> Self {
+        SearchToken {
+            token,
+            position_increment: 1,
+            position_length: 1,
+        }
+    }
+}
+
+/// Returns the tokens of `path` covering exactly the span of `token`, if there are several.
+fn split<'a>(path: &'a [Token], token: &Token) -> Option<&'a [Token]> {
+    let first = path.iter().position(|t| t.start == token.start)?;
+    let len = path[first..].iter().position(|t| t.end == token.end)? + 1;
+    if len > 1 {
+        Some(&path[first..first + len])
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOUN: &str = "名詞,一般,*,*,*,*";
+    const PROPER: &str = "名詞,固有名詞,組織,*,*,*";
+    const PARTICLE: &str = "助詞,格助詞,一般,*,*,*";
+
+    fn token(surface: &str, feature: &str, start: usize) -> Token {
+        Token {
+            surface: surface.to_string(),
+            feature: feature.to_string(),
+            start,
+            end: start + surface.len(),
+            posid: 0,
+            unknown: false,
+        }
+    }
+
+    /// `関西国際空港に`
+    fn best() -> Vec<Token> {
+        vec![token("関西国際空港", PROPER, 0), token("に", PARTICLE, 18)]
+    }
+
+    fn paths() -> Vec<Vec<Token>> {
+        vec![
+            best(),
+            vec![token("関西国際空港に", NOUN, 0)],
+            vec![
+                token("関西", PROPER, 0),
+                token("国際空港", NOUN, 6),
+                token("に", PARTICLE, 18),
+            ],
+            vec![
+                token("関西", PROPER, 0),
+                token("国際", NOUN, 6),
+                token("空港", NOUN, 12),
+                token("に", PARTICLE, 18),
+            ],
+        ]
+    }
+
+    fn summary(tokens: &[SearchToken]) -> Vec<(&str, usize, usize)> {
+        tokens
+            .iter()
+            .map(|t| {
+                (
+                    t.token.surface.as_str(),
+                    t.position_increment,
+                    t.position_length,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_decompound() {
+        let mut requested = None;
+        let tokens = SearchMode::new()
+            .decompound(best(), |n| {
+                requested = Some(n);
+                Ok(paths())
+            })
+            .unwrap();
+        assert_eq!(requested, Some(10));
+        // The first path splitting the compound at different boundaries is chosen.
+        assert_eq!(
+            summary(&tokens),
+            [
+                ("関西国際空港", 1, 2),
+                ("関西", 0, 1),
+                ("国際空港", 1, 1),
+                ("に", 1, 1),
+            ]
+        );
+        assert_eq!((tokens[2].token.start, tokens[2].token.end), (6, 18));
+
+        let mut paths = paths();
+        paths.remove(2);
+        let tokens = SearchMode::new()
+            .nbest(3)
+            .decompound(best(), |n| Ok(paths.into_iter().take(n).collect()))
+            .unwrap();
+        assert_eq!(
+            summary(&tokens),
+            [
+                ("関西国際空港", 1, 3),
+                ("関西", 0, 1),
+                ("国際", 1, 1),
+                ("空港", 1, 1),
+                ("に", 1, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_no_parts() {
+        // No path splits the compound within its span.
+        let tokens = SearchMode::new()
+            .decompound(best(), |_| Ok(vec![best(), paths().remove(1)]))
+            .unwrap();
+        assert_eq!(summary(&tokens), [("関西国際空港", 1, 1), ("に", 1, 1)]);
+    }
+
+    #[test]
+    fn test_compound_tags() {
+        let nbest = |_| -> Result<Vec<Vec<Token>>, MecabError> { panic!("no compounds") };
+        let tokens = SearchMode::new()
+            .compound_tags(&["名詞,一般"])
+            .decompound(best(), nbest)
+            .unwrap();
+        assert_eq!(summary(&tokens), [("関西国際空港", 1, 1), ("に", 1, 1)]);
+
+        let tokens = SearchMode::new()
+            .compound_tags(&["名詞,*,組織"])
+            .decompound(best(), |_| Ok(paths()))
+            .unwrap();
+        assert_eq!(tokens.len(), 4);
+    }
+
+    #[test]
+    fn test_min_length() {
+        let best = vec![token("空港", NOUN, 0)];
+        let parts = vec![vec![token("空", NOUN, 0), token("港", NOUN, 3)]];
+        let nbest = |_| -> Result<Vec<Vec<Token>>, MecabError> { panic!("no compounds") };
+        let tokens = SearchMode::new().decompound(best.clone(), nbest).unwrap();
+        assert_eq!(summary(&tokens), [("空港", 1, 1)]);
+
+        let tokens = SearchMode::new()
+            .min_length(2)
+            .decompound(best, |_| Ok(parts))
+            .unwrap();
+        assert_eq!(
+            summary(&tokens),
+            [("空港", 1, 2), ("空", 0, 1), ("港", 1, 1)]
+        );
+    }
+
+    #[test]
+    fn test_nbest_error() {
+        let result = SearchMode::new().decompound(best(), |_| Err(MecabError::NoMoreResults));
+        match result {
+            Err(MecabError::NoMoreResults) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,310 @@
+//! Sentence segmentation for Japanese text
+//!
+//! MeCab analyzes its input as a single sentence, so documents should be split before tagging.
+//! [`SentenceSplitter`](struct.SentenceSplitter.html) splits text at sentence terminators
+//! outside quotes and brackets, and optionally at newlines.
+
+/// How [`SentenceSplitter`](struct.SentenceSplitter.html) treats newlines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NewlineMode {
+    /// Every newline ends a sentence, even inside quotes.
+    Boundary,
+
+    /// Lines are joined, as in line-wrapped text. Only blank lines end a sentence.
+    Join,
+}
+
+/// Configurable sentence splitter.
+///
+/// A sentence ends after a run of terminators, such as `！？`, and any closing brackets left
+/// unmatched in the sentence. Terminators inside quotes or brackets, as in `「はい。」と答えた。`,
+/// do not end a sentence. An ellipsis (`……`) is not a terminator unless configured so.
+///
+/// Leading and trailing whitespace is excluded from sentences, and empty sentences are
+/// skipped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SentenceSplitter {
+    terminators: Vec<char>,
+    quotes: Vec<(char, char)>,
+    newline: NewlineMode,
+    max_length: Option<usize>,
+}
+
+/// Characters after which an overlong sentence is preferably cut.
+const SOFT_BREAKS: &[char] = &['、', '，', ',', ' ', '\u{3000}', '\t', '\n'];
+
+impl SentenceSplitter {
+    /// Creates a splitter with the defaults for Japanese text: `。．！？!?` are terminators,
+    /// the usual full-width and ASCII brackets are quotes, every newline ends a sentence, and
+    /// the length is unlimited.
+    pub fn new() -> Self {
+        SentenceSplitter {
+            terminators: vec!['。', '．', '！', '？', '!', '?'],
+            quotes: vec![
+                ('「', '」'),
+                ('『', '』'),
+                ('（', '）'),
+                ('(', ')'),
+                ('【', '】'),
+                ('〈', '〉'),
+                ('《', '》'),
+                ('〔', '〕'),
+                ('［', '］'),
+                ('｛', '｝'),
+                ('“', '”'),
+            ],
+            newline: NewlineMode::Boundary,
+            max_length: None,
+        }
+    }
+
+    /// Sets characters which end a sentence.
+    pub fn terminators(mut self, terminators: &[char]) -> Self {
+        self.terminators = terminators.to_vec();
+        self
+    }
+
+    /// Sets pairs of opening and closing characters within which terminators are ignored.
+    /// Pairs may nest.
+    pub fn quotes(mut self, quotes: &[(char, char)]) -> Self {
+        self.quotes = quotes.to_vec();
+        self
+    }
+
+    /// Sets how newlines are treated. Defaults to
+    /// [`NewlineMode::Boundary`](enum.NewlineMode.html#variant.Boundary).
+    pub fn newline(mut self, newline: NewlineMode) -> Self {
+        self.newline = newline;
+        self
+    }
+
+    /// Sets the maximum length of a sentence in bytes. Longer sentences are cut after the
+    /// last comma or whitespace within the limit, or at the limit if there is none.
+    pub fn max_length(mut self, max_length: Option<usize>) -> Self {
+        self.max_length = max_length;
+        self
+    }
+
+    /// Splits `text` into sentences, returning each with its byte offset in `text`.
+    pub fn split<'a>(&self, text: &'a str) -> Vec<(usize, &'a str)> {
+        let mut sentences = Vec::new();
+        let mut closers = Vec::new();
+        let mut start = 0;
+        let mut chars = text.char_indices().peekable();
+        while let Some((pos, c)) = chars.next() {
+            let end = if c == '\n' {
+                if self.newline == NewlineMode::Boundary || is_blank_line(text, pos) {
+                    closers.clear();
+                    Some(pos)
+                } else {
+                    None
+                }
+            } else if closers.last() == Some(&c) {
+                // Checked before openers, so that a pair of the same character can close.
+                closers.pop();
+                None
+            } else if let Some(&(_, closer)) = self.quotes.iter().find(|&&(open, _)| open == c) {
+                closers.push(closer);
+                None
+            } else if let Some(depth) = closers.iter().rposition(|&closer| closer == c) {
+                closers.truncate(depth);
+                None
+            } else if closers.is_empty() && self.terminators.contains(&c) {
+                // Absorb the rest of the terminators and unmatched closing brackets.
+                let mut end = pos + c.len_utf8();
+                while let Some(&(pos, c)) = chars.peek() {
+                    let closing = self.quotes.iter().any(|&(_, close)| close == c)
+                        && !self.quotes.iter().any(|&(open, _)| open == c);
+                    if !closing && !self.terminators.contains(&c) {
+                        break;
+                    }
+                    end = pos + c.len_utf8();
+                    chars.next();
+                }
+                Some(end)
+            } else {
+                None
+            };
+            if let Some(end) = end {
+                self.push(&mut sentences, text, start, end);
+                start = end;
+            }
+        }
+        self.push(&mut sentences, text, start, text.len());
+        sentences
+    }
+
+    /// Trims `text[start..end]` and pushes it, cutting it into pieces of `max_length`.
+    fn push<'a>(
+        &self,
+        sentences: &mut Vec<(usize, &'a str)>,
+        text: &'a str,
+        start: usize,
+        end: usize,
+    ) {
+        let sentence = &text[start..end];
+        let trimmed = sentence.trim_start();
+        let mut start = start + (sentence.len() - trimmed.len());
+        let mut sentence = trimmed.trim_end();
+        while !sentence.is_empty() {
+            let len = match self.max_length {
+                Some(max_length) if sentence.len() > max_length => {
+                    cut_position(sentence, max_length)
+                }
+                _ => sentence.len(),
+            };
+            let piece = sentence[..len].trim_end();
+            if !piece.is_empty() {
+                sentences.push((start, piece));
+            }
+            let rest = sentence[len..].trim_start();
+            start += sentence.len() - rest.len();
+            sentence = rest;
+        }
+    }
+}
+
+impl Default for SentenceSplitter {
+    fn default() -> Self {
+        SentenceSplitter::new()
+    }
+}
+
+/// Whether the newline at `pos` ends a line containing only whitespace.
+fn is_blank_line(text: &str, pos: usize) -> bool {
+    let line_start = text[..pos].rfind('\n').map_or(0, |p| p + 1);
+    text[line_start..pos].trim().is_empty()
+}
+
+/// Returns the byte length of the first piece when cutting `sentence` at `max_length`.
+fn cut_position(sentence: &str, max_length: usize) -> usize {
+    let mut limit = max_length;
+    while !sentence.is_char_boundary(limit) {
+        limit -= 1;
+    }
+    let soft_break = sentence[..limit]
+        .char_indices()
+        .rev()
+        .find(|&(_, c)| SOFT_BREAKS.contains(&c))
+        .map(|(pos, c)| pos + c.len_utf8());
+    match soft_break {
+        Some(pos) => pos,
+        None if limit > 0 => limit,
+        // The first character alone exceeds the limit.
+        None => sentence.chars().next().map_or(0, char::len_utf8),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(splitter: &SentenceSplitter, text: &str) -> Vec<(usize, String)> {
+        splitter
+            .split(text)
+            .into_iter()
+            .map(|(offset, sentence)| (offset, sentence.to_string()))
+            .collect()
+    }
+
+    fn sentences(splitter: &SentenceSplitter, text: &str) -> Vec<String> {
+        split(splitter, text)
+            .into_iter()
+            .map(|(_, sentence)| sentence)
+            .collect()
+    }
+
+    #[test]
+    fn test_terminators() {
+        let splitter = SentenceSplitter::new();
+        assert_eq!(
+            split(&splitter, "晴れた。 散歩した！？  帰った"),
+            vec![
+                (0, "晴れた。".to_string()),
+                (13, "散歩した！？".to_string()),
+                (33, "帰った".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_nested_quotes() {
+        let splitter = SentenceSplitter::new();
+        assert_eq!(
+            sentences(&splitter, "「『はい。』と言え。」と答えた。次へ。"),
+            vec!["「『はい。』と言え。」と答えた。", "次へ。"]
+        );
+        // Unmatched closing brackets end with the sentence.
+        assert_eq!(
+            sentences(&splitter, "そうだ。」と思った。"),
+            vec!["そうだ。」", "と思った。"]
+        );
+    }
+
+    #[test]
+    fn test_same_character_quotes() {
+        let splitter = SentenceSplitter::new()
+            .terminators(&['.'])
+            .quotes(&[('"', '"')]);
+        assert_eq!(
+            sentences(&splitter, "He said \"Hi. Bye.\" Then left. \"Ok.\" Done."),
+            vec!["He said \"Hi. Bye.\" Then left.", "\"Ok.\" Done."]
+        );
+        let splitter = SentenceSplitter::new()
+            .terminators(&['.'])
+            .quotes(&[('"', '"')]);
+        assert_eq!(
+            sentences(&splitter, "A. \"B.\" C."),
+            vec!["A.", "\"B.\" C."]
+        );
+    }
+
+    #[test]
+    fn test_ellipses() {
+        let splitter = SentenceSplitter::new();
+        assert_eq!(
+            sentences(&splitter, "えっと……そうですね。"),
+            vec!["えっと……そうですね。"]
+        );
+        let splitter = SentenceSplitter::new().terminators(&['。', '…']);
+        assert_eq!(
+            sentences(&splitter, "えっと……そうですね。"),
+            vec!["えっと……", "そうですね。"]
+        );
+    }
+
+    #[test]
+    fn test_newlines() {
+        let text = "一行目の\n続き。二文目\n\n三文目";
+        let splitter = SentenceSplitter::new();
+        assert_eq!(
+            sentences(&splitter, text),
+            vec!["一行目の", "続き。", "二文目", "三文目"]
+        );
+        let splitter = SentenceSplitter::new().newline(NewlineMode::Join);
+        assert_eq!(
+            sentences(&splitter, text),
+            vec!["一行目の\n続き。", "二文目", "三文目"]
+        );
+        // A blank line ends an unclosed quote.
+        assert_eq!(
+            sentences(&splitter, "「開いたまま。\n \n次。"),
+            vec!["「開いたまま。", "次。"]
+        );
+    }
+
+    #[test]
+    fn test_max_length() {
+        let splitter = SentenceSplitter::new().max_length(Some(12));
+        assert_eq!(
+            split(&splitter, "あい、うえおかき。"),
+            vec![
+                (0, "あい、".to_string()),
+                (9, "うえおか".to_string()),
+                (21, "き。".to_string())
+            ]
+        );
+        let splitter = SentenceSplitter::new().max_length(Some(2));
+        assert_eq!(sentences(&splitter, "あい"), vec!["あ", "い"]);
+    }
+}
@@ -0,0 +1,348 @@
+//! Tokenization service for [tokio](https://crates.io/crates/tokio) applications
+//!
+//! [`AsyncTokenizer`](struct.AsyncTokenizer.html) runs MeCab on a bounded pool of worker
+//! threads, so that long documents don't block the executor. This module is available with
+//! the `tokio` feature.
+
+use compat::{MecabError, Model};
+use pool::{PooledTagger, TaggerPool};
+use std::error;
+use std::fmt;
+use std::future::Future;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::thread;
+use std::time::Duration;
+use tokenizer::Token;
+use tokio::sync::mpsc::error::{SendError, TrySendError};
+use tokio::sync::mpsc::{self, OwnedPermit};
+use tokio::sync::oneshot;
+use tokio::time::{self, Sleep};
+
+type Job = Box<dyn FnOnce(&TaggerPool) + Send>;
+
+/// Error from [`AsyncTokenizer`](struct.AsyncTokenizer.html).
+#[derive(Debug)]
+pub enum AsyncError {
+    Mecab(MecabError),
+
+    /// The queue was full.
+    Busy,
+
+    /// The timeout elapsed before the work was done.
+    Timeout,
+
+    /// The worker threads stopped, or the work panicked.
+    Closed,
+}
+
+impl fmt::Display for AsyncError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AsyncError::Mecab(e) => write!(f, "MeCab error: {:?}", e),
+            AsyncError::Busy => write!(f, "the tokenizer queue is full"),
+            AsyncError::Timeout => write!(f, "tokenization timed out"),
+            AsyncError::Closed => write!(f, "the tokenizer workers stopped"),
+        }
+    }
+}
+
+impl error::Error for AsyncError {}
+
+impl From<MecabError> for AsyncError {
+    fn from(e: MecabError) -> Self {
+        AsyncError::Mecab(e)
+    }
+}
+
+/// Builder for [`AsyncTokenizer`](struct.AsyncTokenizer.html).
+#[derive(Debug, Clone)]
+pub struct AsyncTokenizerBuilder {
+    model: Arc<Model>,
+    workers: usize,
+    queue_size: usize,
+    timeout: Option<Duration>,
+}
+
+impl AsyncTokenizerBuilder {
+    /// Sets the number of worker threads, each of which uses a tagger. Defaults to the number
+    /// of CPUs.
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.workers = workers;
+        self
+    }
+
+    /// Sets the number of pieces of work waiting for a worker. Defaults to 64.
+    pub fn queue_size(mut self, queue_size: usize) -> Self {
+        self.queue_size = queue_size;
+        self
+    }
+
+    /// Sets the default timeout for each piece of work, including the time in the queue.
+    /// `None` (default) waits indefinitely.
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Starts the worker threads.
+    ///
+    /// ## Panics
+    ///
+    /// Panics if `workers` or `queue_size` is 0, or if a thread cannot be spawned.
+    pub fn build(self) -> AsyncTokenizer {
+        assert!(self.workers > 0, "workers must be positive");
+        assert!(self.queue_size > 0, "queue_size must be positive");
+        let pool = Arc::new(TaggerPool::new(self.model, self.workers));
+        let (sender, receiver) = mpsc::channel::<Job>(self.queue_size);
+        let receiver = Arc::new(Mutex::new(receiver));
+        for i in 0..self.workers {
+            let pool = pool.clone();
+            let receiver = receiver.clone();
+            thread::Builder::new()
+                .name(format!("metalcab-worker-{}", i))
+                .spawn(move || loop {
+                    let job = receiver.lock().unwrap().blocking_recv();
+                    match job {
+                        // A panic drops the reply channel, which the caller sees as `Closed`.
+                        Some(job) => drop(panic::catch_unwind(AssertUnwindSafe(|| job(&pool)))),
+                        None => break,
+                    }
+                })
+                .expect("failed to spawn a worker thread");
+        }
+        AsyncTokenizer {
+            pool,
+            sender,
+            timeout: self.timeout,
+        }
+    }
+}
+
+/// Tokenizer for async code, running MeCab on a pool of worker threads.
+///
+/// Work is queued up to `queue_size`. Beyond that, [`tokenize`](#method.tokenize) waits for
+/// a free slot and [`try_tokenize`](#method.try_tokenize) fails with
+/// [`AsyncError::Busy`](enum.AsyncError.html#variant.Busy), so that callers slow down
+/// instead of piling up work.
+///
+/// Dropping the returned [`Analysis`](struct.Analysis.html) cancels the work unless a worker
+/// has already started it. A timeout works the same way, so it bounds the time spent waiting
+/// but not the time a worker spends on a text it has already started.
+///
+/// The worker threads stop once the tokenizer and all of its clones are dropped.
+///
+/// ```rust,no_run
+/// extern crate metalcab;
+/// extern crate tokio;
+///
+/// use metalcab::compat::Model;
+/// use metalcab::service::AsyncTokenizer;
+/// use std::ffi::CStr;
+/// use std::sync::Arc;
+/// use std::time::Duration;
+///
+/// # fn main() {
+/// let model = Model::create2(CStr::from_bytes_with_nul(b"\0").unwrap()).unwrap();
+/// let tokenizer = AsyncTokenizer::builder(Arc::new(model))
+///     .workers(4)
+///     .timeout(Some(Duration::from_secs(1)))
+///     .build();
+/// let runtime = tokio::runtime::Builder::new_current_thread()
+///     .enable_time()
+///     .build()
+///     .unwrap();
+/// let tokens = runtime
+///     .block_on(tokenizer.tokenize("太郎は本を読んだ。"))
+///     .unwrap();
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct AsyncTokenizer {
+    pool: Arc<TaggerPool>,
+    sender: mpsc::Sender<Job>,
+    timeout: Option<Duration>,
+}
+
+impl AsyncTokenizer {
+    pub fn builder(model: Arc<Model>) -> AsyncTokenizerBuilder {
+        AsyncTokenizerBuilder {
+            model,
+            workers: thread::available_parallelism().map_or(1, |n| n.get()),
+            queue_size: 64,
+            timeout: None,
+        }
+    }
+
+    /// Creates a tokenizer with the default settings.
+    pub fn new(model: Arc<Model>) -> Self {
+        AsyncTokenizer::builder(model).build()
+    }
+
+    pub fn model(&self) -> Arc<Model> {
+        self.pool.model()
+    }
+
+    /// Replaces the model, returning the old one. Work already started finishes with the old
+    /// model.
+    pub fn swap_model(&self, model: Arc<Model>) -> Arc<Model> {
+        self.pool.swap_model(model)
+    }
+
+    /// Tokenizes `text`, waiting for a slot in the queue if it is full.
+    pub fn tokenize<S: Into<String>>(&self, text: S) -> Analysis<Vec<Token>> {
+        let text = text.into();
+        self.run(move |tagger| tagger.tokenize(&text))
+    }
+
+    /// Tokenizes `text`, failing with `AsyncError::Busy` if the queue is full.
+    pub fn try_tokenize<S: Into<String>>(
+        &self,
+        text: S,
+    ) -> Result<Analysis<Vec<Token>>, AsyncError> {
+        let text = text.into();
+        self.try_run(move |tagger| tagger.tokenize(&text))
+    }
+
+    /// Runs `f` on a worker with a tagger and a lattice, waiting for a slot in the queue if it
+    /// is full.
+    pub fn run<T, F>(&self, f: F) -> Analysis<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut PooledTagger) -> Result<T, MecabError> + Send + 'static,
+    {
+        let (job, receiver) = job(f);
+        let reserve = self.sender.clone().reserve_owned();
+        Analysis {
+            state: State::Queued(Box::pin(reserve), Some(job), receiver),
+            timeout: self.timeout,
+            sleep: None,
+        }
+    }
+
+    /// Runs `f` on a worker with a tagger and a lattice, failing with `AsyncError::Busy` if
+    /// the queue is full.
+    pub fn try_run<T, F>(&self, f: F) -> Result<Analysis<T>, AsyncError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut PooledTagger) -> Result<T, MecabError> + Send + 'static,
+    {
+        let (job, receiver) = job(f);
+        match self.sender.try_send(job) {
+            Ok(()) => Ok(Analysis {
+                state: State::Running(receiver),
+                timeout: self.timeout,
+                sleep: None,
+            }),
+            Err(TrySendError::Full(_)) => Err(AsyncError::Busy),
+            Err(TrySendError::Closed(_)) => Err(AsyncError::Closed),
+        }
+    }
+}
+
+/// Wraps `f` into a job replying through a channel.
+fn job<T, F>(f: F) -> (Job, oneshot::Receiver<Result<T, MecabError>>)
+where
+    T: Send + 'static,
+    F: FnOnce(&mut PooledTagger) -> Result<T, MecabError> + Send + 'static,
+{
+    let (sender, receiver) = oneshot::channel();
+    let job = Box::new(move |pool: &TaggerPool| {
+        // Cancelled before a worker picked it up.
+        if sender.is_closed() {
+            return;
+        }
+        let result = pool.get().and_then(|mut tagger| f(&mut tagger));
+        let _ = sender.send(result);
+    });
+    (job, receiver)
+}
+
+type Reserve = Pin<Box<dyn Future<Output = Result<OwnedPermit<Job>, SendError<()>>> + Send>>;
+
+enum State<T> {
+    Queued(
+        Reserve,
+        Option<Job>,
+        oneshot::Receiver<Result<T, MecabError>>,
+    ),
+    Running(oneshot::Receiver<Result<T, MecabError>>),
+    Done,
+}
+
+/// The future of work given to [`AsyncTokenizer`](struct.AsyncTokenizer.html). Dropping it
+/// cancels the work unless it has been started.
+pub struct Analysis<T> {
+    state: State<T>,
+    timeout: Option<Duration>,
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+impl<T> Analysis<T> {
+    /// Overrides the timeout of the tokenizer. The timeout counts from the first poll.
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+impl<T> fmt::Debug for Analysis<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = match self.state {
+            State::Queued(..) => "Queued",
+            State::Running(_) => "Running",
+            State::Done => "Done",
+        };
+        f.debug_struct("Analysis")
+            .field("state", &state)
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+impl<T> Future for Analysis<T> {
+    type Output = Result<T, AsyncError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = &mut *self;
+        if let Some(timeout) = this.timeout.take() {
+            this.sleep = Some(Box::pin(time::sleep(timeout)));
+        }
+        if let Some(ref mut sleep) = this.sleep {
+            if sleep.as_mut().poll(cx).is_ready() {
+                // Drops the receiver, which cancels the work.
+                this.state = State::Done;
+                return Poll::Ready(Err(AsyncError::Timeout));
+            }
+        }
+        loop {
+            match mem::replace(&mut this.state, State::Done) {
+                State::Queued(mut reserve, job, receiver) => match reserve.as_mut().poll(cx) {
+                    Poll::Ready(Ok(permit)) => {
+                        permit.send(job.unwrap());
+                        this.state = State::Running(receiver);
+                    }
+                    Poll::Ready(Err(_)) => return Poll::Ready(Err(AsyncError::Closed)),
+                    Poll::Pending => {
+                        this.state = State::Queued(reserve, job, receiver);
+                        return Poll::Pending;
+                    }
+                },
+                State::Running(mut receiver) => match Pin::new(&mut receiver).poll(cx) {
+                    Poll::Ready(Ok(result)) => {
+                        return Poll::Ready(result.map_err(AsyncError::from))
+                    }
+                    Poll::Ready(Err(_)) => return Poll::Ready(Err(AsyncError::Closed)),
+                    Poll::Pending => {
+                        this.state = State::Running(receiver);
+                        return Poll::Pending;
+                    }
+                },
+                State::Done => panic!("Analysis polled after completion"),
+            }
+        }
+    }
+}
@@ -0,0 +1,258 @@
+//! Owned snapshots of analysis results
+//!
+//! [`Node`][compat::Node], [`Path`][compat::Path] and
+//! [`DictionaryInfo`][compat::DictionaryInfo] borrow from the tagger or the lattice. The types
+//! in this module copy them out, so that results can be stored or sent elsewhere. With the
+//! `serde` feature, they implement `Serialize` and `Deserialize`, as do
+//! [`Token`][tokenizer::Token] and [`DictionaryType`][compat::DictionaryType].
+//!
+//! [compat::DictionaryInfo]: ../compat/struct.DictionaryInfo.html
+//! [compat::DictionaryType]: ../compat/enum.DictionaryType.html
+//! [compat::Node]: ../compat/struct.Node.html
+//! [compat::Path]: ../compat/struct.Path.html
+//! [tokenizer::Token]: ../tokenizer/struct.Token.html
+
+use compat::{DictionaryInfo, DictionaryType, Lattice, MecabError, Node};
+use tokenizer::{Token, Tokenizer};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// A copy of a [`Node`][compat::Node].
+///
+/// [compat::Node]: ../compat/struct.Node.html
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct NodeSnapshot {
+    /// The unique id of the node in the lattice.
+    pub id: u32,
+
+    /// The surface form. Empty for BOS/EOS nodes.
+    pub surface: String,
+
+    pub feature: String,
+
+    /// The byte offset of the surface in the sentence, if known.
+    pub start: Option<usize>,
+
+    /// The byte length of the surface.
+    pub length: u16,
+
+    /// The byte length of the surface including the preceding whitespace.
+    pub rlength: u16,
+
+    pub rc_attr: u16,
+    pub lc_attr: u16,
+    pub posid: u16,
+
+    /// One of `MECAB_NOR_NODE`, `MECAB_UNK_NODE`, `MECAB_BOS_NODE`, `MECAB_EOS_NODE` and
+    /// `MECAB_EON_NODE`.
+    pub stat: u8,
+
+    /// Whether the node is on the best path.
+    pub isbest: bool,
+
+    /// The forward accumulative log summation, available with marginal probabilities.
+    pub alpha: f32,
+
+    /// The backward accumulative log summation, available with marginal probabilities.
+    pub beta: f32,
+
+    /// The marginal probability, available with marginal probabilities.
+    pub prob: f32,
+
+    /// The word cost.
+    pub wcost: i16,
+
+    /// The best accumulative cost from the BOS node to this node.
+    pub cost: i64,
+}
+
+impl NodeSnapshot {
+    /// Copies a node, whose surface is at `start` in the sentence if known.
+    pub fn new(node: &Node, start: Option<usize>) -> Self {
+        let surface = node.surface_bytes().unwrap_or(b"");
+        NodeSnapshot {
+            id: node.id(),
+            surface: String::from_utf8_lossy(surface).into_owned(),
+            feature: node.feature().unwrap_or("").to_string(),
+            start,
+            length: node.length(),
+            rlength: node.rlength(),
+            rc_attr: node.rcAttr(),
+            lc_attr: node.lcAttr(),
+            posid: node.posid(),
+            stat: node.stat(),
+            isbest: node.isbest(),
+            alpha: node.alpha(),
+            beta: node.beta(),
+            prob: node.prob(),
+            wcost: node.wcost() as i16,
+            cost: node.cost(),
+        }
+    }
+}
+
+/// A copy of a [`Path`][compat::Path], the connection between two nodes.
+///
+/// [compat::Path]: ../compat/struct.Path.html
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PathSnapshot {
+    /// The id of the left node.
+    pub lnode: u32,
+
+    /// The id of the right node.
+    pub rnode: u32,
+
+    /// The connection cost.
+    pub cost: i32,
+
+    /// The marginal probability, available with marginal probabilities.
+    pub prob: f32,
+}
+
+/// A copy of all the nodes and paths in a parsed [`Lattice`][compat::Lattice].
+///
+/// [compat::Lattice]: ../compat/struct.Lattice.html
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct LatticeSnapshot {
+    pub sentence: String,
+
+    /// All the nodes, including BOS and EOS, in the order of their positions.
+    pub nodes: Vec<NodeSnapshot>,
+
+    /// All the paths. MeCab records them only if the lattice was parsed with `MECAB_NBEST`
+    /// or `MECAB_MARGINAL_PROB`.
+    pub paths: Vec<PathSnapshot>,
+
+    /// The ids of the nodes on the best path, from BOS to EOS.
+    pub best: Vec<u32>,
+}
+
+impl LatticeSnapshot {
+    /// Copies a lattice parsed with
+    /// [`Tagger::parse_lattice`][compat::Tagger::parse_lattice].
+    ///
+    /// [compat::Tagger::parse_lattice]: ../compat/struct.Tagger.html#method.parse_lattice
+    pub fn new(lattice: &Lattice) -> Self {
+        let mut nodes = Vec::new();
+        let mut paths = Vec::new();
+        let mut add = |node: &Node, start: Option<usize>| {
+            nodes.push(NodeSnapshot::new(node, start));
+            let mut path = node.lpath();
+            while let Some(p) = path {
+                paths.push(PathSnapshot {
+                    lnode: p.lnode().map_or(0, |n| n.id()),
+                    rnode: node.id(),
+                    cost: p.cost(),
+                    prob: p.prob(),
+                });
+                path = p.lnext();
+            }
+        };
+
+        if let Some(bos) = lattice.bos_node() {
+            add(bos, Some(0));
+        }
+        let size = lattice.size();
+        for (pos, &first) in lattice.all_begin_nodes().iter().enumerate().take(size + 1) {
+            let mut node = first;
+            while let Some(n) = node {
+                add(n, Some(pos + n.rlength() as usize - n.length() as usize));
+                node = n.bnext();
+            }
+        }
+
+        let mut best = Vec::new();
+        let mut node = lattice.bos_node();
+        while let Some(n) = node {
+            best.push(n.id());
+            node = n.next();
+        }
+        LatticeSnapshot {
+            sentence: lattice.sentence().unwrap_or("").to_string(),
+            nodes,
+            paths,
+            best,
+        }
+    }
+}
+
+/// The N best paths of a sentence.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct NBestList {
+    pub text: String,
+
+    /// The tokens of each path, best first.
+    pub paths: Vec<Vec<Token>>,
+}
+
+impl NBestList {
+    /// Tokenizes `text` into the `n` best paths with
+    /// [`Tokenizer::tokenize_nbest`][tokenizer::Tokenizer::tokenize_nbest].
+    ///
+    /// [tokenizer::Tokenizer::tokenize_nbest]: ../tokenizer/struct.Tokenizer.html#method.tokenize_nbest
+    pub fn new(tokenizer: &mut Tokenizer, text: &str, n: usize) -> Result<Self, MecabError> {
+        Ok(NBestList {
+            text: text.to_string(),
+            paths: tokenizer.tokenize_nbest(text, n)?,
+        })
+    }
+}
+
+/// A copy of a [`DictionaryInfo`][compat::DictionaryInfo].
+///
+/// [compat::DictionaryInfo]: ../compat/struct.DictionaryInfo.html
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct DictionaryInfoSnapshot {
+    /// The file name, with invalid UTF-8 replaced.
+    pub filename: String,
+
+    pub charset: String,
+
+    /// The number of words.
+    pub size: u32,
+
+    #[cfg_attr(feature = "serde", serde(rename = "type"))]
+    pub type_: DictionaryType,
+
+    /// The size of the left attributes.
+    pub lsize: u32,
+
+    /// The size of the right attributes.
+    pub rsize: u32,
+
+    pub version: u16,
+}
+
+impl DictionaryInfoSnapshot {
+    pub fn new(info: &DictionaryInfo) -> Self {
+        DictionaryInfoSnapshot {
+            filename: String::from_utf8_lossy(info.filename().unwrap_or(b"")).into_owned(),
+            charset: String::from_utf8_lossy(info.charset().unwrap_or(b"")).into_owned(),
+            size: info.size(),
+            type_: info.type_(),
+            lsize: info.lsize(),
+            rsize: info.rsize(),
+            version: info.version(),
+        }
+    }
+
+    /// Copies a linked list of dictionaries, as returned by
+    /// [`Model::dictionary_info`][compat::Model::dictionary_info].
+    ///
+    /// [compat::Model::dictionary_info]: ../compat/struct.Model.html#method.dictionary_info
+    pub fn list(first: Option<&DictionaryInfo>) -> Vec<Self> {
+        let mut list = Vec::new();
+        let mut info = first;
+        while let Some(i) = info {
+            list.push(DictionaryInfoSnapshot::new(i));
+            info = i.next();
+        }
+        list
+    }
+}
@@ -0,0 +1,323 @@
+//! Tokenizer for [tantivy](https://crates.io/crates/tantivy)
+//!
+//! [`MecabTokenizer`](struct.MecabTokenizer.html) implements tantivy's `Tokenizer`, so that
+//! it can be registered to an index with `index.tokenizers().register("ja", tokenizer)`.
+//! This module is available with the `tantivy` feature.
+//!
+//! With [`search_mode`](struct.MecabTokenizer.html#method.search_mode), compound nouns are
+//! indexed together with their parts, as described in the [`search`](../search/index.html)
+//! module.
+
+use compat::{MecabError, Model};
+use pool::TaggerPool;
+use search::{SearchMode, SearchToken};
+use std::sync::Arc;
+use std::thread;
+use tantivy_tokenizer_api::{self as api, TokenStream};
+use tokenizer::{pos_matches, split_features};
+
+/// A tantivy tokenizer backed by a MeCab [`Model`][compat::Model].
+///
+/// The tokenizer and its clones, which tantivy's indexing threads use, share a
+/// [`TaggerPool`][pool::TaggerPool], so that each text is tokenized with a tagger of its own.
+/// The taggers are released once the tokenizer and all of its clones are dropped, and
+/// replaced by taggers of the new model after
+/// [`TaggerPool::swap_model`][pool::TaggerPool::swap_model].
+///
+/// Token offsets are the byte offsets in the text, and positions are consecutive among the
+/// tokens not filtered out. In search mode, the parts of a compound take the positions
+/// spanned by the compound, whose `position_length` is the number of parts.
+///
+/// ```rust,no_run
+/// extern crate metalcab;
+/// extern crate tantivy_tokenizer_api;
+///
+/// use metalcab::compat::Model;
+/// use metalcab::tantivy::MecabTokenizer;
+/// use std::ffi::CStr;
+/// use std::sync::Arc;
+/// use tantivy_tokenizer_api::{TokenStream, Tokenizer};
+///
+/// # fn main() {
+/// let model = Model::create2(CStr::from_bytes_with_nul(b"\0").unwrap()).unwrap();
+/// let mut tokenizer = MecabTokenizer::new(Arc::new(model))
+///     .base_form(Some(6))
+///     .stop_tags(&["助詞", "助動詞", "記号"]);
+/// let mut stream = tokenizer.token_stream("太郎は本を読んだ。");
+/// while let Some(token) = stream.next() {
+///     println!("{} {}..{}", token.text, token.offset_from, token.offset_to);
+/// }
+/// # }
+/// ```
+///
+/// [compat::Model]: ../compat/struct.Model.html
+/// [pool::TaggerPool]: ../pool/struct.TaggerPool.html
+/// [pool::TaggerPool::swap_model]: ../pool/struct.TaggerPool.html#method.swap_model
+#[derive(Debug, Clone)]
+pub struct MecabTokenizer {
+    pool: Arc<TaggerPool>,
+    base_form: Option<usize>,
+    stop_tags: Vec<Vec<String>>,
+    search_mode: Option<SearchMode>,
+}
+
+impl MecabTokenizer {
+    /// Creates a tokenizer with a pool of as many taggers as CPUs.
+    pub fn new(model: Arc<Model>) -> Self {
+        let cpus = thread::available_parallelism().map_or(1, |n| n.get());
+        MecabTokenizer::with_pool(Arc::new(TaggerPool::new(model, cpus)))
+    }
+
+    /// Creates a tokenizer borrowing taggers from `pool`.
+    pub fn with_pool(pool: Arc<TaggerPool>) -> Self {
+        MecabTokenizer {
+            pool,
+            base_form: None,
+            stop_tags: Vec::new(),
+            search_mode: None,
+        }
+    }
+
+    pub fn model(&self) -> Arc<Model> {
+        self.pool.model()
+    }
+
+    pub fn pool(&self) -> &Arc<TaggerPool> {
+        &self.pool
+    }
+
+    /// Replaces token texts with the feature field at `field`, e.g., 6 for IPADIC and 7 for
+    /// UniDic, unless the field is missing or `*`. `None` (default) keeps the surfaces.
+    pub fn base_form(mut self, field: Option<usize>) -> Self {
+        self.base_form = field;
+        self
+    }
+
+    /// Drops tokens by part of speech. Each tag is a comma-separated prefix of the feature
+    /// fields, where `*` matches any field, e.g., `助詞` or `名詞,*,*,*,*`.
+    pub fn stop_tags(mut self, tags: &[&str]) -> Self {
+        self.stop_tags = tags
+            .iter()
+            .map(|tag| split_features(tag).iter().map(|f| f.to_string()).collect())
+            .collect();
+        self
+    }
+
+    /// Decompounds compound nouns with `search_mode`. `None` (default) emits the best path
+    /// only.
+    pub fn search_mode(mut self, search_mode: Option<SearchMode>) -> Self {
+        self.search_mode = search_mode;
+        self
+    }
+
+    /// Tokenizes `text` into tantivy tokens.
+    pub fn tokenize(&self, text: &str) -> Result<Vec<api::Token>, MecabError> {
+        let mut tagger = self.pool.get()?;
+        let tokens = match self.search_mode {
+            Some(ref search_mode) => search_mode.tokenize_pooled(&mut tagger, text)?,
+            None => tagger
+                .tokenize(text)?
+                .into_iter()
+                .map(SearchToken::from)
+                .collect(),
+        };
+        Ok(tantivy_tokens(tokens, self.base_form, &self.stop_tags))
+    }
+}
+
+/// Converts tokens into tantivy tokens, dropping the ones matching `stop_tags` and replacing
+/// the surfaces with the `base_form` field.
+fn tantivy_tokens(
+    tokens: Vec<SearchToken>,
+    base_form: Option<usize>,
+    stop_tags: &[Vec<String>],
+) -> Vec<api::Token> {
+    let mut result = Vec::with_capacity(tokens.len());
+    let mut last = None;
+    for search_token in tokens {
+        let token = search_token.token;
+        let features = split_features(&token.feature);
+        if stop_tags.iter().any(|tag| pos_matches(tag, &features)) {
+            continue;
+        }
+        let text = match base_form.and_then(|i| features.get(i)) {
+            Some(&base) if !base.is_empty() && base != "*" => base.to_string(),
+            _ => token.surface.clone(),
+        };
+        let position = match last {
+            Some(last) if search_token.position_increment == 0 => last,
+            Some(last) => last + 1,
+            None => 0,
+        };
+        last = Some(position);
+        result.push(api::Token {
+            offset_from: token.start,
+            offset_to: token.end,
+            position,
+            text,
+            position_length: search_token.position_length,
+        });
+    }
+    result
+}
+
+impl api::Tokenizer for MecabTokenizer {
+    type TokenStream<'a> = MecabTokenStream;
+
+    /// Returns an empty stream if MeCab fails, since tantivy's tokenizers cannot report
+    /// errors. Use [`tokenize`](#method.tokenize) to handle them.
+    fn token_stream<'a>(&'a mut self, text: &'a str) -> MecabTokenStream {
+        MecabTokenStream::new(self.tokenize(text).unwrap_or_default())
+    }
+}
+
+/// The token stream of [`MecabTokenizer`](struct.MecabTokenizer.html).
+#[derive(Debug, Clone)]
+pub struct MecabTokenStream {
+    tokens: Vec<api::Token>,
+    index: usize,
+}
+
+impl MecabTokenStream {
+    pub fn new(tokens: Vec<api::Token>) -> Self {
+        MecabTokenStream { tokens, index: 0 }
+    }
+}
+
+impl TokenStream for MecabTokenStream {
+    fn advance(&mut self) -> bool {
+        if self.index < self.tokens.len() {
+            self.index += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn token(&self) -> &api::Token {
+        &self.tokens[self.index - 1]
+    }
+
+    fn token_mut(&mut self) -> &mut api::Token {
+        &mut self.tokens[self.index - 1]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokenizer::Token;
+
+    fn token(surface: &str, feature: &str, start: usize, end: usize) -> SearchToken {
+        SearchToken::from(Token {
+            surface: surface.to_string(),
+            feature: feature.to_string(),
+            start,
+            end,
+            posid: 0,
+            unknown: false,
+        })
+    }
+
+    /// `太郎は本を読んだ。`
+    fn sentence() -> Vec<SearchToken> {
+        vec![
+            token("太郎", "名詞,固有名詞,人名,名,*,*,太郎,タロウ,タロー", 0, 6),
+            token("は", "助詞,係助詞,*,*,*,*,は,ハ,ワ", 6, 9),
+            token("本", "名詞,一般,*,*,*,*,本,ホン,ホン", 9, 12),
+            token("を", "助詞,格助詞,一般,*,*,*,を,ヲ,ヲ", 12, 15),
+            token(
+                "読ん",
+                "動詞,自立,*,*,五段・マ行,連用タ接続,読む,ヨン,ヨン",
+                15,
+                21,
+            ),
+            token("だ", "助動詞,*,*,*,特殊・タ,基本形,だ,ダ,ダ", 21, 24),
+            token("。", "記号,句点,*,*,*,*,。,。,。", 24, 27),
+        ]
+    }
+
+    fn stop_tags(tags: &[&str]) -> Vec<Vec<String>> {
+        tags.iter()
+            .map(|tag| split_features(tag).iter().map(|f| f.to_string()).collect())
+            .collect()
+    }
+
+    fn summary(tokens: &[api::Token]) -> Vec<(&str, usize, usize, usize, usize)> {
+        tokens
+            .iter()
+            .map(|token| {
+                (
+                    token.text.as_str(),
+                    token.position,
+                    token.position_length,
+                    token.offset_from,
+                    token.offset_to,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_offsets_and_positions() {
+        let tokens = tantivy_tokens(sentence(), None, &[]);
+        assert_eq!(
+            summary(&tokens),
+            [
+                ("太郎", 0, 1, 0, 6),
+                ("は", 1, 1, 6, 9),
+                ("本", 2, 1, 9, 12),
+                ("を", 3, 1, 12, 15),
+                ("読ん", 4, 1, 15, 21),
+                ("だ", 5, 1, 21, 24),
+                ("。", 6, 1, 24, 27),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_base_form() {
+        let mut tokens = sentence();
+        // A missing or `*` field keeps the surface.
+        tokens[1].token.feature = "助詞,係助詞,*,*,*,*,*".to_string();
+        tokens[3].token.feature = "助詞,格助詞".to_string();
+        let tokens = tantivy_tokens(tokens, Some(6), &[]);
+        let texts: Vec<_> = tokens.iter().map(|token| token.text.as_str()).collect();
+        assert_eq!(texts, ["太郎", "は", "本", "を", "読む", "だ", "。"]);
+        assert_eq!((tokens[4].offset_from, tokens[4].offset_to), (15, 21));
+    }
+
+    #[test]
+    fn test_stop_tags() {
+        let tags = stop_tags(&["助詞", "助動詞", "記号,句点", "名詞,*,人名"]);
+        let tokens = tantivy_tokens(sentence(), Some(6), &tags);
+        assert_eq!(
+            summary(&tokens),
+            [("本", 0, 1, 9, 12), ("読む", 1, 1, 15, 21)]
+        );
+
+        // Longer tags than the features don't match.
+        let tags = stop_tags(&["助詞,係助詞,*,*,*,*,は,ハ,ワ,*"]);
+        assert_eq!(tantivy_tokens(sentence(), None, &tags).len(), 7);
+    }
+
+    #[test]
+    fn test_compound_positions() {
+        let mut compound = token("東京都庁", "名詞,固有名詞,組織,*,*,*,東京都庁", 0, 12);
+        compound.position_length = 2;
+        let mut tokyo = token("東京都", "名詞,固有名詞,地域,一般,*,*,東京都", 0, 9);
+        tokyo.position_increment = 0;
+        let chou = token("庁", "名詞,接尾,一般,*,*,*,庁", 9, 12);
+        let ni = token("に", "助詞,格助詞,一般,*,*,*,に,ニ,ニ", 12, 15);
+        let tokens = tantivy_tokens(vec![compound, tokyo, chou, ni], None, &[]);
+        assert_eq!(
+            summary(&tokens),
+            [
+                ("東京都庁", 0, 2, 0, 12),
+                ("東京都", 0, 1, 0, 9),
+                ("庁", 1, 1, 9, 12),
+                ("に", 2, 1, 12, 15),
+            ]
+        );
+    }
+}
@@ -0,0 +1,623 @@
+//! Higher-level tokenization API
+//!
+//! This module wraps [`Tagger`][compat::Tagger] to produce owned [`Token`](struct.Token.html)s
+//! carrying byte offsets into the input.
+//!
+//! [compat::Tagger]: ../compat/struct.Tagger.html
+
+use compat::{Lattice, MecabError, Node, Tagger};
+use raw::{MECAB_BOS_NODE, MECAB_EOS_NODE, MECAB_UNK_NODE};
+use sentence::SentenceSplitter;
+use std::collections::VecDeque;
+use std::error;
+use std::fmt;
+use std::io::{self, BufRead};
+use std::iter;
+use std::str::{self, Utf8Error};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// A morpheme with its position in the input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Token {
+    /// The surface form.
+    pub surface: String,
+
+    /// The feature string, e.g., `名詞,固有名詞,人名,名,*,*,太郎,タロウ,タロー`.
+    pub feature: String,
+
+    /// The byte offset where the surface begins.
+    pub start: usize,
+
+    /// The byte offset where the surface ends.
+    pub end: usize,
+
+    /// The part-of-speech id, as defined in the `pos-id.def` file.
+    pub posid: u16,
+
+    /// Whether the morpheme is not defined in the dictionary.
+    pub unknown: bool,
+}
+
+impl Token {
+    /// Creates a token from a node whose surface points into `text`, placing it at `offset`
+    /// plus its position in `text`.
+    ///
+    /// Returns `None` for BOS/EOS nodes.
+    pub fn from_node(node: &Node, text: &str, offset: usize) -> Option<Self> {
+        let stat = node.stat();
+        if stat == MECAB_BOS_NODE || stat == MECAB_EOS_NODE {
+            return None;
+        }
+        let surface = node.surface_bytes().unwrap_or(b"");
+        let start = (surface.as_ptr() as usize).wrapping_sub(text.as_ptr() as usize);
+        let (start, surface) = if start <= text.len() && surface.len() <= text.len() - start {
+            (start, &text[start..start + surface.len()])
+        } else {
+            // The surface is not a part of `text`, e.g., when MECAB_ALLOCATE_SENTENCE is set.
+            (0, str::from_utf8(surface).unwrap_or(""))
+        };
+        Some(Token {
+            surface: surface.to_string(),
+            feature: node.feature().unwrap_or("").to_string(),
+            start: offset + start,
+            end: offset + start + surface.len(),
+            posid: node.posid(),
+            unknown: stat == MECAB_UNK_NODE,
+        })
+    }
+
+    /// Splits the feature string into fields at commas, honoring double-quoted fields.
+    pub fn features(&self) -> Vec<&str> {
+        split_features(&self.feature)
+    }
+}
+
+/// Splits a feature string into fields at commas, honoring double-quoted fields.
+pub fn split_features(feature: &str) -> Vec<&str> {
+    fn unquote(field: &str) -> &str {
+        if field.len() >= 2 && field.starts_with('"') && field.ends_with('"') {
+            &field[1..field.len() - 1]
+        } else {
+            field
+        }
+    }
+
+    let mut fields = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    for (pos, c) in feature.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                fields.push(unquote(&feature[start..pos]));
+                start = pos + 1;
+            }
+            _ => {}
+        }
+    }
+    fields.push(unquote(&feature[start..]));
+    fields
+}
+
+/// Whether the feature fields begin with `tag`, a prefix of fields where `*` matches any field.
+pub(crate) fn pos_matches(tag: &[String], features: &[&str]) -> bool {
+    tag.len() <= features.len() && tag.iter().zip(features).all(|(t, f)| t == "*" || t == f)
+}
+
+/// Collects the tokens of the best path in a lattice parsed after
+/// [`Lattice::set_sentence`][compat::Lattice::set_sentence]. Token offsets are relative to
+/// the sentence.
+///
+/// [compat::Lattice::set_sentence]: ../compat/struct.Lattice.html#method.set_sentence
+pub(crate) fn lattice_tokens(lattice: &Lattice) -> Vec<Token> {
+    // The lattice holds a copy of the sentence, which the surfaces point into.
+    let sentence = lattice.sentence().unwrap_or("");
+    let mut tokens = Vec::new();
+    let mut node = lattice.bos_node();
+    while let Some(n) = node {
+        tokens.extend(Token::from_node(n, sentence, 0));
+        node = n.next();
+    }
+    tokens
+}
+
+/// Tokenizer producing owned tokens.
+#[derive(Debug)]
+pub struct Tokenizer<'model> {
+    tagger: Tagger<'model>,
+}
+
+impl<'model> Tokenizer<'model> {
+    pub fn new(tagger: Tagger<'model>) -> Self {
+        Tokenizer { tagger }
+    }
+
+    pub fn tagger(&self) -> &Tagger<'model> {
+        &self.tagger
+    }
+
+    pub fn tagger_mut(&mut self) -> &mut Tagger<'model> {
+        &mut self.tagger
+    }
+
+    pub fn into_tagger(self) -> Tagger<'model> {
+        self.tagger
+    }
+
+    /// Tokenizes `text`. Token offsets are relative to `text`.
+    pub fn tokenize(&mut self, text: &str) -> Result<Vec<Token>, MecabError> {
+        self.tokenize_at(text, 0)
+    }
+
+    /// Tokenizes `text`, adding `offset` to token offsets.
+    pub fn tokenize_at(&mut self, text: &str, offset: usize) -> Result<Vec<Token>, MecabError> {
+        let mut tokens = Vec::new();
+        let mut node = Some(self.tagger.parseToNode(text)?);
+        while let Some(n) = node {
+            tokens.extend(Token::from_node(n, text, offset));
+            node = n.next();
+        }
+        Ok(tokens)
+    }
+
+    /// Tokenizes `text` into the `n` best paths, best first. Token offsets are relative to
+    /// `text`.
+    ///
+    /// The lattice level of the tagger is raised to 1 during the call if it is lower.
+    pub fn tokenize_nbest(&mut self, text: &str, n: usize) -> Result<Vec<Vec<Token>>, MecabError> {
+        let lattice_level = self.tagger.lattice_level();
+        if lattice_level < 1 {
+            self.tagger.set_lattice_level(1);
+        }
+        let result = self.tokenize_nbest_inner(text, n);
+        self.tagger.set_lattice_level(lattice_level);
+        result
+    }
+
+    fn tokenize_nbest_inner(
+        &mut self,
+        text: &str,
+        limit: usize,
+    ) -> Result<Vec<Vec<Token>>, MecabError> {
+        let mut paths = Vec::new();
+        self.tagger.parseNBestInit(text)?;
+        while paths.len() < limit {
+            let mut tokens = Vec::new();
+            let mut node = match self.tagger.nextNode() {
+                Ok(node) => Some(node),
+                Err(MecabError::NoMoreResults) if !paths.is_empty() => break,
+                Err(e) => return Err(e),
+            };
+            while let Some(n) = node {
+                tokens.extend(Token::from_node(n, text, 0));
+                node = n.next();
+            }
+            paths.push(tokens);
+        }
+        Ok(paths)
+    }
+
+    /// Splits `text` into sentences with `splitter` and tokenizes each of them. Token offsets
+    /// are relative to `text`.
+    pub fn tokenize_sentences(
+        &mut self,
+        text: &str,
+        splitter: &SentenceSplitter,
+    ) -> Result<Vec<Sentence>, MecabError> {
+        splitter
+            .split(text)
+            .into_iter()
+            .map(|(offset, sentence)| {
+                Ok(Sentence {
+                    offset,
+                    text: sentence.to_string(),
+                    tokens: self.tokenize_at(sentence, offset)?,
+                })
+            })
+            .collect()
+    }
+
+    /// Splits `text` into sentences with `splitter` and concatenates the results of
+    /// [`Tagger::parse`][compat::Tagger::parse] for each of them.
+    ///
+    /// [compat::Tagger::parse]: ../compat/struct.Tagger.html#method.parse
+    pub fn parse_sentences(
+        &mut self,
+        text: &str,
+        splitter: &SentenceSplitter,
+    ) -> Result<String, MecabError> {
+        let mut result = String::new();
+        for (_, sentence) in splitter.split(text) {
+            result.push_str(self.tagger.parse(sentence)?);
+        }
+        Ok(result)
+    }
+
+    /// Tokenizes UTF-8 text read incrementally from `reader`.
+    ///
+    /// The input is split into sentences at newlines and, if configured by
+    /// [`Stream::terminators`](struct.Stream.html#method.terminators), after sentence
+    /// terminators.
+    pub fn stream<R: BufRead>(&mut self, reader: R) -> Stream<'_, 'model, R> {
+        Stream {
+            tokenizer: self,
+            sentences: SentenceReader::new(reader),
+        }
+    }
+}
+
+impl<'model> From<Tagger<'model>> for Tokenizer<'model> {
+    fn from(tagger: Tagger<'model>) -> Self {
+        Tokenizer::new(tagger)
+    }
+}
+
+/// How [`Stream`](struct.Stream.html) handles lines which are not valid UTF-8.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InvalidUtf8 {
+    /// Yield an error. The stream continues from the next line.
+    Error,
+
+    /// Replace invalid sequences with U+FFFD, as `String::from_utf8_lossy` does. Offsets
+    /// still refer to the input, where a replacement character spans the bytes it replaces.
+    Lossy,
+
+    /// Skip the line.
+    SkipLine,
+}
+
+/// A sentence and its tokens, as produced by [`Stream`](struct.Stream.html) and
+/// [`Tokenizer::tokenize_sentences`](struct.Tokenizer.html#method.tokenize_sentences).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Sentence {
+    /// The byte offset of the sentence in the input.
+    pub offset: usize,
+
+    /// The sentence text, without the line terminator.
+    pub text: String,
+
+    /// The tokens, with byte offsets into the input.
+    pub tokens: Vec<Token>,
+}
+
+/// Iterator tokenizing a `BufRead` sentence by sentence.
+///
+/// This is created by [`Tokenizer::stream`](struct.Tokenizer.html#method.stream).
+#[derive(Debug)]
+pub struct Stream<'t, 'model: 't, R> {
+    tokenizer: &'t mut Tokenizer<'model>,
+    sentences: SentenceReader<R>,
+}
+
+impl<'t, 'model: 't, R: BufRead> Stream<'t, 'model, R> {
+    /// Sets characters which end a sentence within a line, e.g., `。！？`.
+    pub fn terminators(mut self, terminators: &[char]) -> Self {
+        self.sentences.terminators = terminators.to_vec();
+        self
+    }
+
+    /// Sets how lines which are not valid UTF-8 are handled. Defaults to
+    /// [`InvalidUtf8::Error`](enum.InvalidUtf8.html#variant.Error).
+    pub fn invalid_utf8(mut self, invalid_utf8: InvalidUtf8) -> Self {
+        self.sentences.invalid_utf8 = invalid_utf8;
+        self
+    }
+}
+
+impl<'t, 'model: 't, R: BufRead> Iterator for Stream<'t, 'model, R> {
+    type Item = Result<Sentence, StreamError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let sentence = match self.sentences.next()? {
+            Ok(sentence) => sentence,
+            Err(e) => return Some(Err(e)),
+        };
+        let tokens = match sentence.offsets {
+            None => self.tokenizer.tokenize_at(&sentence.text, sentence.offset),
+            Some(ref offsets) => self.tokenizer.tokenize(&sentence.text).map(|mut tokens| {
+                for token in &mut tokens {
+                    token.start = offsets[token.start];
+                    token.end = offsets[token.end];
+                }
+                tokens
+            }),
+        };
+        Some(
+            tokens
+                .map(|tokens| Sentence {
+                    offset: sentence.offset,
+                    text: sentence.text,
+                    tokens,
+                })
+                .map_err(StreamError::from),
+        )
+    }
+}
+
+/// A sentence read by [`SentenceReader`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct RawSentence {
+    offset: usize,
+    text: String,
+    /// The offset in the input of each byte of `text` and of its end, if the text was decoded
+    /// lossily and so has different offsets.
+    offsets: Option<Vec<usize>>,
+}
+
+/// Reads a `BufRead` line by line, and splits the lines into sentences.
+#[derive(Debug)]
+struct SentenceReader<R> {
+    reader: R,
+    terminators: Vec<char>,
+    invalid_utf8: InvalidUtf8,
+    offset: usize,
+    line: Vec<u8>,
+    pending: VecDeque<RawSentence>,
+    done: bool,
+}
+
+impl<R: BufRead> SentenceReader<R> {
+    fn new(reader: R) -> Self {
+        SentenceReader {
+            reader,
+            terminators: Vec::new(),
+            invalid_utf8: InvalidUtf8::Error,
+            offset: 0,
+            line: Vec::new(),
+            pending: VecDeque::new(),
+            done: false,
+        }
+    }
+
+    /// Reads the next line and queues its sentences.
+    fn read_line(&mut self) -> Result<(), StreamError> {
+        self.line.clear();
+        let offset = self.offset;
+        let len = self.reader.read_until(b'\n', &mut self.line)?;
+        self.offset += len;
+        if len == 0 {
+            self.done = true;
+            return Ok(());
+        }
+        let mut line = &self.line[..];
+        if line.ends_with(b"\n") {
+            line = &line[..line.len() - 1];
+        }
+        if line.ends_with(b"\r") {
+            line = &line[..line.len() - 1];
+        }
+        let (line, offsets) = match str::from_utf8(line) {
+            Ok(line) => (line.to_string(), None),
+            Err(error) => match self.invalid_utf8 {
+                InvalidUtf8::Error => return Err(StreamError::InvalidUtf8 { offset, error }),
+                InvalidUtf8::Lossy => {
+                    let (line, offsets) = decode_lossy(line);
+                    (line, Some(offsets))
+                }
+                InvalidUtf8::SkipLine => return Ok(()),
+            },
+        };
+        let mut start = 0;
+        for (pos, c) in line.char_indices() {
+            if self.terminators.contains(&c) {
+                let end = pos + c.len_utf8();
+                self.push(offset, &line, offsets.as_ref(), start, end);
+                start = end;
+            }
+        }
+        if start < line.len() {
+            self.push(offset, &line, offsets.as_ref(), start, line.len());
+        }
+        Ok(())
+    }
+
+    /// Queues `line[start..end]`, where `line` begins at `offset` in the input.
+    fn push(
+        &mut self,
+        offset: usize,
+        line: &str,
+        offsets: Option<&Vec<usize>>,
+        start: usize,
+        end: usize,
+    ) {
+        let offsets = offsets.map(|offsets| {
+            offsets[start..=end]
+                .iter()
+                .map(|&o| offset + o)
+                .collect::<Vec<_>>()
+        });
+        self.pending.push_back(RawSentence {
+            offset: offsets
+                .as_ref()
+                .map_or(offset + start, |offsets| offsets[0]),
+            text: line[start..end].to_string(),
+            offsets,
+        });
+    }
+}
+
+impl<R: BufRead> Iterator for SentenceReader<R> {
+    type Item = Result<RawSentence, StreamError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pending.is_empty() {
+            if self.done {
+                return None;
+            }
+            if let Err(e) = self.read_line() {
+                return Some(Err(e));
+            }
+        }
+        self.pending.pop_front().map(Ok)
+    }
+}
+
+/// Decodes `bytes` as `String::from_utf8_lossy` does, and returns the offset in `bytes` of
+/// each byte of the result and of its end.
+fn decode_lossy(mut bytes: &[u8]) -> (String, Vec<usize>) {
+    let mut text = String::with_capacity(bytes.len());
+    let mut offsets = Vec::with_capacity(bytes.len() + 1);
+    let mut offset = 0;
+    loop {
+        let (valid, invalid) = match str::from_utf8(bytes) {
+            Ok(_) => (bytes.len(), 0),
+            Err(error) => (
+                error.valid_up_to(),
+                error
+                    .error_len()
+                    .unwrap_or(bytes.len() - error.valid_up_to()),
+            ),
+        };
+        text.push_str(str::from_utf8(&bytes[..valid]).unwrap());
+        offsets.extend(offset..offset + valid);
+        if invalid == 0 {
+            offsets.push(offset + valid);
+            return (text, offsets);
+        }
+        let replacement = '\u{FFFD}';
+        text.push(replacement);
+        offsets.extend(iter::repeat_n(offset + valid, replacement.len_utf8()));
+        offset += valid + invalid;
+        bytes = &bytes[valid + invalid..];
+    }
+}
+
+/// Error from [`Stream`](struct.Stream.html).
+#[derive(Debug)]
+pub enum StreamError {
+    Io(io::Error),
+    Mecab(MecabError),
+    /// A line is not valid UTF-8.
+    InvalidUtf8 {
+        /// The byte offset of the line in the stream.
+        offset: usize,
+        error: Utf8Error,
+    },
+}
+
+impl fmt::Display for StreamError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StreamError::Io(ref e) => write!(f, "I/O error: {}", e),
+            StreamError::Mecab(ref e) => write!(f, "MeCab error: {:?}", e),
+            StreamError::InvalidUtf8 { offset, ref error } => {
+                write!(f, "invalid UTF-8 in the line at byte {}: {}", offset, error)
+            }
+        }
+    }
+}
+
+impl error::Error for StreamError {}
+
+impl From<io::Error> for StreamError {
+    fn from(e: io::Error) -> Self {
+        StreamError::Io(e)
+    }
+}
+
+impl From<MecabError> for StreamError {
+    fn from(e: MecabError) -> Self {
+        StreamError::Mecab(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::BufReader;
+
+    /// Reads `input` through a buffer of two bytes, so that lines span several reads.
+    fn read(input: &[u8], terminators: &[char], invalid_utf8: InvalidUtf8) -> Vec<RawSentence> {
+        let mut reader = SentenceReader::new(BufReader::with_capacity(2, input));
+        reader.terminators = terminators.to_vec();
+        reader.invalid_utf8 = invalid_utf8;
+        reader.map(|sentence| sentence.unwrap()).collect()
+    }
+
+    fn sentence(offset: usize, text: &str) -> RawSentence {
+        RawSentence {
+            offset,
+            text: text.to_string(),
+            offsets: None,
+        }
+    }
+
+    #[test]
+    fn test_lines() {
+        let input = "今日は。\r\n\r\n晴れ\n雨";
+        assert_eq!(
+            read(input.as_bytes(), &[], InvalidUtf8::Error),
+            [
+                sentence(0, "今日は。"),
+                sentence(16, "晴れ"),
+                sentence(23, "雨"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_terminators() {
+        let input = "晴れ。雨！\nどう？";
+        assert_eq!(
+            read(input.as_bytes(), &['。', '！', '？'], InvalidUtf8::Error),
+            [
+                sentence(0, "晴れ。"),
+                sentence(9, "雨！"),
+                sentence(16, "どう？"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_invalid_utf8_error() {
+        let input = b"a\n\xffb\nc\n";
+        let mut reader = SentenceReader::new(&input[..]);
+        assert_eq!(reader.next().unwrap().unwrap(), sentence(0, "a"));
+        match reader.next() {
+            Some(Err(StreamError::InvalidUtf8 { offset: 2, .. })) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert_eq!(reader.next().unwrap().unwrap(), sentence(5, "c"));
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn test_invalid_utf8_skip_line() {
+        let input = b"a\n\xffb\nc\n";
+        assert_eq!(
+            read(input, &[], InvalidUtf8::SkipLine),
+            [sentence(0, "a"), sentence(5, "c")]
+        );
+    }
+
+    #[test]
+    fn test_invalid_utf8_lossy() {
+        // A truncated `あ` (E3 81 82) and an invalid byte.
+        let input = b"a\nb\xe3\x81c\xff\xe3\x80\x82d";
+        let sentences = read(input, &['。'], InvalidUtf8::Lossy);
+        assert_eq!(
+            sentences,
+            [
+                sentence(0, "a"),
+                RawSentence {
+                    offset: 2,
+                    text: "b\u{FFFD}c\u{FFFD}。".to_string(),
+                    offsets: Some(vec![2, 3, 3, 3, 5, 6, 6, 6, 7, 8, 9, 10]),
+                },
+                RawSentence {
+                    offset: 10,
+                    text: "d".to_string(),
+                    offsets: Some(vec![10, 11]),
+                },
+            ]
+        );
+        assert_eq!(
+            decode_lossy(b"\xe3\x81"),
+            ("\u{FFFD}".to_string(), vec![0, 0, 0, 2])
+        );
+    }
+}
@@ -0,0 +1,646 @@
+//! Running MeCab's command-line tools
+//!
+//! The tools exposed by libmecab, such as `mecab-dict-index` and `mecab-cost-train`, print
+//! diagnostics to stdout/stderr and terminate the whole process on many errors. This module
+//! runs them in a forked child process instead, capturing the output and the exit status.
+//!
+//! The child runs the tool's `main` without `exec`. Only the forking thread is copied into
+//! the child, so a lock held by another thread at the time of `fork`, such as one in `malloc`,
+//! stdio or the C++ streams, stays locked in the child, which then hangs on its first use.
+//! Avoid allocating, printing or using MeCab from other threads while spawning tools, or
+//! spawn them before starting other threads.
+//!
+//! This module is only available on Unix.
+
+use compat::MecabError;
+use libc::{self, c_char, c_int};
+use raw;
+use std::error;
+use std::ffi::{CString, OsStr};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+/// Held while creating the pipes for a child and forking it, so that children spawned at the
+/// same time don't start with each other's pipes.
+static SPAWN_LOCK: Mutex<()> = Mutex::new(());
+
+/// A command-line tool implemented in libmecab.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Tool {
+    /// `mecab`, implemented by `mecab_do`.
+    Mecab,
+    /// `mecab-dict-index`, implemented by `mecab_dict_index`.
+    DictIndex,
+    /// `mecab-dict-gen`, implemented by `mecab_dict_gen`.
+    DictGen,
+    /// `mecab-cost-train`, implemented by `mecab_cost_train`.
+    CostTrain,
+    /// `mecab-system-eval`, implemented by `mecab_system_eval`.
+    SystemEval,
+    /// `mecab-test-gen`, implemented by `mecab_test_gen`.
+    TestGen,
+}
+
+impl Tool {
+    /// Return the program name, which is passed as `argv[0]`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Tool::Mecab => "mecab",
+            Tool::DictIndex => "mecab-dict-index",
+            Tool::DictGen => "mecab-dict-gen",
+            Tool::CostTrain => "mecab-cost-train",
+            Tool::SystemEval => "mecab-system-eval",
+            Tool::TestGen => "mecab-test-gen",
+        }
+    }
+
+    fn entry(self) -> unsafe extern "C" fn(c_int, *mut *mut c_char) -> c_int {
+        match self {
+            Tool::Mecab => raw::mecab_do,
+            Tool::DictIndex => raw::mecab_dict_index,
+            Tool::DictGen => raw::mecab_dict_gen,
+            Tool::CostTrain => raw::mecab_cost_train,
+            Tool::SystemEval => raw::mecab_system_eval,
+            Tool::TestGen => raw::mecab_test_gen,
+        }
+    }
+}
+
+/// The result of a finished tool.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ToolOutput {
+    /// The exit status.
+    pub status: i32,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+impl ToolOutput {
+    pub fn success(&self) -> bool {
+        self.status == 0
+    }
+
+    /// Converts a non-zero exit status into
+    /// [`ToolError::Failed`](enum.ToolError.html#variant.Failed).
+    pub fn check(self) -> Result<Self, ToolError> {
+        if self.success() {
+            return Ok(self);
+        }
+        let stderr = String::from_utf8_lossy(&self.stderr);
+        let stdout = String::from_utf8_lossy(&self.stdout);
+        let message = last_diagnostic(&stderr).or_else(|| last_diagnostic(&stdout));
+        Err(ToolError::Failed {
+            status: self.status,
+            error: message.map_or(MecabError::UnknownError, MecabError::from),
+        })
+    }
+}
+
+/// Finds the last error message, such as `dictionary.cpp(123) [cond] message`, in the output.
+fn last_diagnostic(output: &str) -> Option<&str> {
+    let lines = output
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty());
+    let mut last = None;
+    for line in lines {
+        let is_diagnostic = line.find(") [").is_some_and(|pos| {
+            let location = &line[..pos];
+            location.contains(".cpp(") || location.contains(".h(")
+        });
+        if is_diagnostic || last.is_none() {
+            last = Some(line);
+        }
+    }
+    last
+}
+
+/// Error from running a tool.
+#[derive(Debug)]
+pub enum ToolError {
+    Io(io::Error),
+    /// A required input file or directory does not exist.
+    NotFound(PathBuf),
+    /// An argument is rejected before running the tool.
+    InvalidArgument(String),
+    /// The tool exited with a non-zero status. `error` is classified from its last diagnostic.
+    Failed {
+        status: i32,
+        error: MecabError,
+    },
+    /// The tool was terminated by a signal.
+    Signaled(i32),
+    /// The tool was killed through a [`KillHandle`](struct.KillHandle.html).
+    Cancelled,
+}
+
+impl fmt::Display for ToolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ToolError::Io(ref e) => write!(f, "I/O error: {}", e),
+            ToolError::NotFound(ref path) => write!(f, "not found: {}", path.display()),
+            ToolError::InvalidArgument(ref message) => write!(f, "invalid argument: {}", message),
+            ToolError::Failed { status, ref error } => {
+                write!(f, "exited with status {}: {:?}", status, error)
+            }
+            ToolError::Signaled(signal) => write!(f, "terminated by signal {}", signal),
+            ToolError::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+impl error::Error for ToolError {}
+
+impl From<io::Error> for ToolError {
+    fn from(e: io::Error) -> Self {
+        ToolError::Io(e)
+    }
+}
+
+/// A tool running in a child process.
+///
+/// The child is forked from this process and runs the tool's `main` without `exec`. This is
+/// only safe if no other thread holds a lock, e.g., in `malloc` or stdio, when the process
+/// forks; otherwise the child may hang. See the [module documentation](index.html).
+#[derive(Debug)]
+pub struct ToolProcess {
+    handle: KillHandle,
+    stdin: Option<JoinHandle<io::Result<()>>>,
+    stdout: Option<JoinHandle<io::Result<Vec<u8>>>>,
+    stderr: Option<JoinHandle<io::Result<Vec<u8>>>>,
+}
+
+impl ToolProcess {
+    /// Forks the process and runs `tool` with `args` (excluding `argv[0]`) in the child,
+    /// feeding `stdin` to it.
+    pub fn spawn<I, S>(tool: Tool, args: I, stdin: &[u8]) -> Result<Self, ToolError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        ToolProcess::spawn_observed(tool, args, stdin, |_| true)
+    }
+
+    /// Same as [`spawn`](#method.spawn), but calls `observer` with each line printed to
+    /// stdout, as it is printed. The child is killed when `observer` returns `false`.
+    pub fn spawn_observed<I, S, F>(
+        tool: Tool,
+        args: I,
+        stdin: &[u8],
+        observer: F,
+    ) -> Result<Self, ToolError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+        F: FnMut(&str) -> bool + Send + 'static,
+    {
+        let mut argv = vec![CString::new(tool.name()).unwrap()];
+        for arg in args {
+            let arg = CString::new(arg.as_ref().as_bytes())
+                .map_err(|_| ToolError::InvalidArgument("argument contains NUL".to_string()))?;
+            argv.push(arg);
+        }
+        let mut argv_ptrs = argv
+            .iter()
+            .map(|arg| arg.as_ptr() as *mut c_char)
+            .collect::<Vec<_>>();
+        argv_ptrs.push(ptr::null_mut());
+        let argc = argv.len() as c_int;
+
+        let guard = SPAWN_LOCK
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let (stdin_r, stdin_w) = pipe()?;
+        let (stdout_r, stdout_w) = pipe()?;
+        let (stderr_r, stderr_w) = pipe()?;
+        let entry = tool.entry();
+
+        let pid = unsafe { libc::fork() };
+        if pid < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        if pid == 0 {
+            // The child: redirect the standard streams and run the tool. Every other file
+            // descriptor is closed, so that the tool sees EOF on stdin and doesn't keep the
+            // pipes of other children open, and `exit` flushes the C and C++ streams.
+            unsafe {
+                libc::dup2(stdin_r.as_raw_fd(), 0);
+                libc::dup2(stdout_w.as_raw_fd(), 1);
+                libc::dup2(stderr_w.as_raw_fd(), 2);
+                close_fds_from(3);
+                let status = entry(argc, argv_ptrs.as_mut_ptr());
+                libc::exit(status);
+            }
+        }
+
+        drop(stdin_r);
+        drop(stdout_w);
+        drop(stderr_w);
+        drop(guard);
+        let stdin = stdin.to_vec();
+        let handle = KillHandle {
+            pid,
+            reaped: Arc::new(Mutex::new(false)),
+            cancelled: Arc::new(Mutex::new(false)),
+        };
+        Ok(ToolProcess {
+            stdout: Some(read_lines(stdout_r, observer, handle.clone())),
+            handle,
+            stdin: Some(thread::spawn(move || {
+                let mut stdin_w = stdin_w;
+                match stdin_w.write_all(&stdin) {
+                    // The tool may exit without reading its input.
+                    Err(ref e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+                    result => result,
+                }
+            })),
+            stderr: Some(read_to_end(stderr_r)),
+        })
+    }
+
+    /// Return the process id of the child.
+    pub fn id(&self) -> u32 {
+        self.handle.pid as u32
+    }
+
+    /// Return a handle to kill the child from another thread.
+    pub fn kill_handle(&self) -> KillHandle {
+        self.handle.clone()
+    }
+
+    /// Kills the child. `wait` then returns
+    /// [`ToolError::Cancelled`](enum.ToolError.html#variant.Cancelled), unless the child had
+    /// already exited.
+    pub fn kill(&self) -> io::Result<()> {
+        self.handle.kill()
+    }
+
+    /// Waits for the child to exit and collects its output.
+    pub fn wait(mut self) -> Result<ToolOutput, ToolError> {
+        let status = self.handle.wait()?;
+        let stdin = self.stdin.take().unwrap();
+        let stdout = self.stdout.take().unwrap();
+        let stderr = self.stderr.take().unwrap();
+        stdin.join().expect("stdin writer panicked")?;
+        let stdout = stdout.join().expect("stdout reader panicked")?;
+        let stderr = stderr.join().expect("stderr reader panicked")?;
+        if *self.handle.cancelled.lock().unwrap() {
+            return Err(ToolError::Cancelled);
+        }
+        if libc::WIFSIGNALED(status) {
+            return Err(ToolError::Signaled(libc::WTERMSIG(status)));
+        }
+        Ok(ToolOutput {
+            status: libc::WEXITSTATUS(status),
+            stdout,
+            stderr,
+        })
+    }
+}
+
+impl Drop for ToolProcess {
+    fn drop(&mut self) {
+        // Do not leave a zombie if the process is dropped without `wait`.
+        if self.stdout.is_some() {
+            let _ = self.handle.kill();
+            let _ = self.handle.wait();
+        }
+    }
+}
+
+/// A handle to kill a [`ToolProcess`](struct.ToolProcess.html), which can be sent to other
+/// threads.
+#[derive(Debug, Clone)]
+pub struct KillHandle {
+    pid: libc::pid_t,
+    /// Whether the child has been reaped, after which its pid may be reused.
+    reaped: Arc<Mutex<bool>>,
+    cancelled: Arc<Mutex<bool>>,
+}
+
+impl KillHandle {
+    /// Kills the child with `SIGKILL`. Does nothing if it has already exited.
+    pub fn kill(&self) -> io::Result<()> {
+        let reaped = self.reaped.lock().unwrap();
+        if *reaped || self.exited()? {
+            return Ok(());
+        }
+        *self.cancelled.lock().unwrap() = true;
+        if unsafe { libc::kill(self.pid, libc::SIGKILL) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Whether the child has exited, without reaping it.
+    fn exited(&self) -> io::Result<bool> {
+        let mut info: libc::siginfo_t = unsafe { mem::zeroed() };
+        let options = libc::WEXITED | libc::WNOHANG | libc::WNOWAIT;
+        if unsafe { libc::waitid(libc::P_PID, self.pid as libc::id_t, &mut info, options) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // `si_pid` stays 0 if the child is still running.
+        Ok(unsafe { info.si_pid() } != 0)
+    }
+
+    /// Waits for the child to exit, reaping it, and returns the raw wait status.
+    fn wait(&self) -> io::Result<c_int> {
+        // Wait without reaping first, so that `kill` never signals a reused pid.
+        loop {
+            let mut info: libc::siginfo_t = unsafe { mem::zeroed() };
+            let options = libc::WEXITED | libc::WNOWAIT;
+            if unsafe { libc::waitid(libc::P_PID, self.pid as libc::id_t, &mut info, options) } == 0
+            {
+                break;
+            }
+            let e = io::Error::last_os_error();
+            if e.kind() != io::ErrorKind::Interrupted {
+                return Err(e);
+            }
+        }
+        let mut reaped = self.reaped.lock().unwrap();
+        let mut status = 0;
+        if unsafe { libc::waitpid(self.pid, &mut status, 0) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        *reaped = true;
+        Ok(status)
+    }
+}
+
+/// Runs `tool` with `args` (excluding `argv[0]`) to completion.
+///
+/// The tool runs in a forked child without `exec`, which may hang if another thread holds a
+/// lock, e.g., in `malloc` or stdio, when the process forks. See
+/// [`ToolProcess`](struct.ToolProcess.html).
+pub fn run<I, S>(tool: Tool, args: I, stdin: &[u8]) -> Result<ToolOutput, ToolError>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    ToolProcess::spawn(tool, args, stdin)?.wait()
+}
+
+/// Runs the `mecab` command-line front end with `args` (excluding `argv[0]`), feeding it
+/// `stdin` and writing what it prints to stdout into `stdout`.
+///
+/// The front end runs in a child process like the other tools, because it writes to the file
+/// descriptors of the process and exits on errors. Its behavior is otherwise that of the
+/// `mecab` command linked with the same libmecab, so the output can be compared with that of
+/// [`Tagger`][compat::Tagger] exactly.
+///
+/// ```rust,no_run
+/// use metalcab::tools::{run_cli, split_options};
+///
+/// let mut output = Vec::new();
+/// let args = split_options("-Owakati -N2").unwrap();
+/// run_cli(&args, "すもももももももものうち\n".as_bytes(), &mut output).unwrap();
+/// print!("{}", String::from_utf8(output).unwrap());
+/// ```
+///
+/// [compat::Tagger]: ../compat/struct.Tagger.html
+pub fn run_cli<I, S, W>(args: I, stdin: &[u8], stdout: &mut W) -> Result<(), ToolError>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+    W: Write,
+{
+    let output = run(Tool::Mecab, args, stdin)?;
+    stdout.write_all(&output.stdout)?;
+    output.check()?;
+    Ok(())
+}
+
+/// Splits an option string, such as `-d "/opt/my dic" -Owakati`, into arguments as a POSIX
+/// shell would, without expansions.
+///
+/// Fails with [`ToolError::InvalidArgument`](enum.ToolError.html#variant.InvalidArgument) if a
+/// quotation is not closed.
+pub fn split_options(options: &str) -> Result<Vec<String>, ToolError> {
+    let mut args = Vec::new();
+    let mut arg = None;
+    let mut chars = options.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                let arg = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => arg.push(c),
+                        None => return Err(unclosed(options)),
+                    }
+                }
+            }
+            '"' => {
+                let arg = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ '"') | Some(c @ '\\') | Some(c @ '$') | Some(c @ '`') => {
+                                arg.push(c)
+                            }
+                            Some('\n') => {}
+                            Some(c) => {
+                                arg.push('\\');
+                                arg.push(c);
+                            }
+                            None => return Err(unclosed(options)),
+                        },
+                        Some(c) => arg.push(c),
+                        None => return Err(unclosed(options)),
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                Some('\n') => {}
+                Some(c) => arg.get_or_insert_with(String::new).push(c),
+                None => arg.get_or_insert_with(String::new).push('\\'),
+            },
+            c if c.is_whitespace() => args.extend(arg.take()),
+            c => arg.get_or_insert_with(String::new).push(c),
+        }
+    }
+    args.extend(arg);
+    Ok(args)
+}
+
+fn unclosed(options: &str) -> ToolError {
+    ToolError::InvalidArgument(format!("unclosed quotation in {:?}", options))
+}
+
+/// Checks that an input file exists.
+pub(crate) fn require_file(path: &Path) -> Result<(), ToolError> {
+    if path.is_file() {
+        Ok(())
+    } else {
+        Err(ToolError::NotFound(path.to_path_buf()))
+    }
+}
+
+/// Checks that an input directory exists.
+pub(crate) fn require_dir(path: &Path) -> Result<(), ToolError> {
+    if path.is_dir() {
+        Ok(())
+    } else {
+        Err(ToolError::NotFound(path.to_path_buf()))
+    }
+}
+
+/// Checks that the directory of an output file exists.
+pub(crate) fn require_parent_dir(path: &Path) -> Result<(), ToolError> {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => require_dir(parent),
+        _ => Ok(()),
+    }
+}
+
+/// Creates a pipe whose ends are closed on `exec`, so that processes spawned otherwise, e.g.,
+/// by `std::process::Command`, don't inherit them. Children forked by
+/// [`ToolProcess`](struct.ToolProcess.html) close them explicitly.
+///
+/// Where `pipe2` is available, the flag is set atomically. Elsewhere, a process spawned by
+/// another thread between `pipe` and `fcntl` inherits the ends, and the reader of the pipe
+/// doesn't see EOF until that process exits.
+fn pipe() -> io::Result<(File, File)> {
+    let mut fds = [0; 2];
+    #[cfg(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "netbsd",
+        target_os = "openbsd",
+        target_os = "dragonfly"
+    ))]
+    {
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    #[cfg(not(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "netbsd",
+        target_os = "openbsd",
+        target_os = "dragonfly"
+    )))]
+    {
+        if unsafe { libc::pipe(fds.as_mut_ptr()) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        for &fd in &fds {
+            unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+        }
+    }
+    Ok(unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) })
+}
+
+/// Closes every file descriptor from `low` on. Only async-signal-safe functions are called,
+/// so that it can run in a forked child.
+unsafe fn close_fds_from(low: c_int) {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        let (low, high) = (low as libc::c_uint, libc::c_uint::MAX);
+        if libc::syscall(libc::SYS_close_range, low, high, 0) == 0 {
+            return;
+        }
+    }
+    let max = match libc::sysconf(libc::_SC_OPEN_MAX) {
+        max if max > 0 => max.min(c_int::MAX as libc::c_long) as c_int,
+        _ => 1024,
+    };
+    for fd in low..max {
+        libc::close(fd);
+    }
+}
+
+fn read_lines<F>(file: File, mut observer: F, handle: KillHandle) -> JoinHandle<io::Result<Vec<u8>>>
+where
+    F: FnMut(&str) -> bool + Send + 'static,
+{
+    thread::spawn(move || {
+        let mut reader = BufReader::new(file);
+        let mut buf = Vec::new();
+        let mut observing = true;
+        loop {
+            let start = buf.len();
+            if reader.read_until(b'\n', &mut buf)? == 0 {
+                return Ok(buf);
+            }
+            if observing && !observer(String::from_utf8_lossy(&buf[start..]).trim_end()) {
+                observing = false;
+                handle.kill()?;
+            }
+        }
+    })
+}
+
+fn read_to_end(mut file: File) -> JoinHandle<io::Result<Vec<u8>>> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf)?;
+        Ok(buf)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(options: &str) -> Vec<String> {
+        split_options(options).unwrap()
+    }
+
+    #[test]
+    fn test_split_options() {
+        assert_eq!(split("-d /opt/dic"), vec!["-d", "/opt/dic"]);
+        assert_eq!(split("--dicdir=/opt/dic"), vec!["--dicdir=/opt/dic"]);
+        assert_eq!(split("--all-morphs"), vec!["--all-morphs"]);
+        assert_eq!(split("  -a\t-N2 \n"), vec!["-a", "-N2"]);
+        assert_eq!(split(""), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_split_bundled_and_unknown_options() {
+        // Options are not interpreted, so bundled and unknown ones are passed as they are.
+        assert_eq!(split("-Owakati -aN2"), vec!["-Owakati", "-aN2"]);
+        assert_eq!(
+            split("--no-such-option -z 1"),
+            vec!["--no-such-option", "-z", "1"]
+        );
+    }
+
+    #[test]
+    fn test_split_quotes() {
+        assert_eq!(
+            split(r#"-d "/opt/my dic" -u '/tmp/a b.dic'"#),
+            vec!["-d", "/opt/my dic", "-u", "/tmp/a b.dic"]
+        );
+        assert_eq!(
+            split(r#"--dicdir="/opt/my dic""#),
+            vec!["--dicdir=/opt/my dic"]
+        );
+        assert_eq!(split(r#""a\"b\\c\d" ''"#), vec![r#"a"b\c\d"#, ""]);
+        assert_eq!(split(r"a\ b c\\"), vec!["a b", r"c\"]);
+    }
+
+    #[test]
+    fn test_split_unclosed_quotes() {
+        for options in &[r#"-d "/opt"#, "-d '/opt", r#"-d "/opt\"#] {
+            match split_options(options) {
+                Err(ToolError::InvalidArgument(_)) => {}
+                other => panic!("{}: unexpected result {:?}", options, other),
+            }
+        }
+    }
+}
@@ -0,0 +1,485 @@
+//! Training models
+//!
+//! [`CostTrainer`](struct.CostTrainer.html) is a typed front end of `mecab-cost-train`, which
+//! trains a CRF model from an annotated corpus. Training runs in a child process, so that it
+//! can be cancelled from another thread.
+//!
+//! [`Retrainer`](struct.Retrainer.html) chains training with
+//! [`DictionaryGenerator`][dictionary::DictionaryGenerator] and
+//! [`DictionaryCompiler`][dictionary::DictionaryCompiler] to rebuild a system dictionary from
+//! the corpus.
+//!
+//! This module is only available on Unix.
+//!
+//! [dictionary::DictionaryCompiler]: ../dictionary/struct.DictionaryCompiler.html
+//! [dictionary::DictionaryGenerator]: ../dictionary/struct.DictionaryGenerator.html
+
+use dictionary::DictionaryGenerator;
+use std::error;
+use std::ffi::OsString;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tools::{require_dir, require_file, require_parent_dir};
+use tools::{KillHandle, Tool, ToolError, ToolOutput, ToolProcess};
+
+/// The default of `-e` in `mecab-cost-train`.
+const DEFAULT_ETA: f64 = 0.00005;
+
+/// The number of iterations in a row with `diff` below eta after which `mecab-cost-train`
+/// stops.
+const CONVERGING_ITERATIONS: usize = 3;
+
+/// The statistics printed by `mecab-cost-train` after each iteration.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Progress {
+    /// The iteration number, counted from 0.
+    pub iteration: usize,
+
+    /// The ratio of wrongly tagged nodes in the training corpus.
+    pub error_rate: f64,
+
+    /// The F-measure of the tagging of the training corpus.
+    pub f_measure: f64,
+
+    /// The value of the objective function.
+    pub objective: f64,
+
+    /// The relative change of the objective function. Training converges when this falls
+    /// below eta in three iterations in a row.
+    pub diff: f64,
+}
+
+impl Progress {
+    /// Parses a line such as `iter=3 err=0.0213 F=0.9702 target=1234.5 diff=0.0012`.
+    ///
+    /// Returns `None` if the line is not a progress line.
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut iteration = None;
+        let mut error_rate = None;
+        let mut f_measure = None;
+        let mut objective = None;
+        let mut diff = None;
+        for field in line.split_whitespace() {
+            let mut kv = field.splitn(2, '=');
+            let (key, value) = (kv.next()?, kv.next()?);
+            match key {
+                "iter" => iteration = value.parse().ok(),
+                "err" => error_rate = value.parse().ok(),
+                "F" => f_measure = value.parse().ok(),
+                "target" => objective = value.parse().ok(),
+                "diff" => diff = value.parse().ok(),
+                _ => {}
+            }
+        }
+        Some(Progress {
+            iteration: iteration?,
+            error_rate: error_rate?,
+            f_measure: f_measure.unwrap_or(0.0),
+            objective: objective?,
+            diff: diff?,
+        })
+    }
+}
+
+/// The result of a successful training.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrainingResult {
+    /// The path of the trained model.
+    pub model: PathBuf,
+
+    /// The statistics of each iteration.
+    pub progress: Vec<Progress>,
+
+    /// The messages printed by `mecab-cost-train`.
+    pub output: ToolOutput,
+}
+
+impl TrainingResult {
+    /// Return the number of iterations until convergence.
+    pub fn iterations(&self) -> usize {
+        self.progress
+            .last()
+            .map_or(0, |progress| progress.iteration + 1)
+    }
+}
+
+/// Error from [`CostTrainer`](struct.CostTrainer.html).
+#[derive(Debug)]
+pub enum TrainError {
+    Tool(ToolError),
+    /// Training was stopped because it did not converge within the iteration limit.
+    IterationLimit {
+        /// The statistics of the last iteration.
+        last: Progress,
+    },
+}
+
+impl fmt::Display for TrainError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TrainError::Tool(ref e) => write!(f, "mecab-cost-train: {}", e),
+            TrainError::IterationLimit { ref last } => write!(
+                f,
+                "not converged in {} iterations (diff={})",
+                last.iteration + 1,
+                last.diff
+            ),
+        }
+    }
+}
+
+impl error::Error for TrainError {}
+
+impl From<ToolError> for TrainError {
+    fn from(e: ToolError) -> Self {
+        TrainError::Tool(e)
+    }
+}
+
+/// Trains a CRF model with `mecab-cost-train`.
+///
+/// ```rust,no_run
+/// use metalcab::train::CostTrainer;
+///
+/// let result = CostTrainer::new("corpus.txt", "seed", "model")
+///     .c(1.0)
+///     .threads(4)
+///     .max_iterations(Some(500))
+///     .train()
+///     .unwrap();
+/// println!("converged in {} iterations", result.iterations());
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct CostTrainer {
+    corpus: PathBuf,
+    dicdir: PathBuf,
+    model: PathBuf,
+    old_model: Option<PathBuf>,
+    c: Option<f64>,
+    eta: Option<f64>,
+    frequency_cutoff: Option<u32>,
+    threads: Option<usize>,
+    max_iterations: Option<usize>,
+}
+
+impl CostTrainer {
+    /// Trains a model from `corpus`, the output of `mecab` annotated by hand, with the
+    /// seed dictionary in `dicdir`, i.e., the CSV sources, `feature.def`, `rewrite.def` and so
+    /// on. The model is written to `model`.
+    pub fn new<P, Q, R>(corpus: P, dicdir: Q, model: R) -> Self
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+        R: AsRef<Path>,
+    {
+        CostTrainer {
+            corpus: corpus.as_ref().to_path_buf(),
+            dicdir: dicdir.as_ref().to_path_buf(),
+            model: model.as_ref().to_path_buf(),
+            old_model: None,
+            c: None,
+            eta: None,
+            frequency_cutoff: None,
+            threads: None,
+            max_iterations: None,
+        }
+    }
+
+    pub fn dicdir(&self) -> &Path {
+        &self.dicdir
+    }
+
+    pub fn model(&self) -> &Path {
+        &self.model
+    }
+
+    /// Sets a model to start from (`-M`).
+    pub fn old_model<P: AsRef<Path>>(mut self, old_model: P) -> Self {
+        self.old_model = Some(old_model.as_ref().to_path_buf());
+        self
+    }
+
+    /// Sets the hyperparameter C (`-c`), which trades off fitting against regularization.
+    /// MeCab defaults to 1.0.
+    pub fn c(mut self, c: f64) -> Self {
+        self.c = Some(c);
+        self
+    }
+
+    /// Sets the tolerance of the termination criterion (`-e`). MeCab defaults to 0.00005.
+    pub fn eta(mut self, eta: f64) -> Self {
+        self.eta = Some(eta);
+        self
+    }
+
+    /// Sets the frequency below which features are ignored (`-f`). MeCab defaults to 1.
+    pub fn frequency_cutoff(mut self, cutoff: u32) -> Self {
+        self.frequency_cutoff = Some(cutoff);
+        self
+    }
+
+    /// Sets the number of threads (`-p`). MeCab defaults to 1.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = Some(threads);
+        self
+    }
+
+    /// Sets the maximum number of iterations.
+    ///
+    /// `mecab-cost-train` has no such option and writes the model only on convergence, so
+    /// training exceeding the limit is killed and reported as
+    /// [`TrainError::IterationLimit`](enum.TrainError.html#variant.IterationLimit).
+    pub fn max_iterations(mut self, max_iterations: Option<usize>) -> Self {
+        self.max_iterations = max_iterations;
+        self
+    }
+
+    /// Return the arguments passed to `mecab-cost-train`, excluding `argv[0]`.
+    pub fn args(&self) -> Vec<OsString> {
+        let mut args = vec![OsString::from("-d"), self.dicdir.clone().into_os_string()];
+        if let Some(ref old_model) = self.old_model {
+            args.push("-M".into());
+            args.push(old_model.clone().into_os_string());
+        }
+        if let Some(c) = self.c {
+            args.push("-c".into());
+            args.push(c.to_string().into());
+        }
+        if let Some(eta) = self.eta {
+            args.push("-e".into());
+            args.push(eta.to_string().into());
+        }
+        if let Some(cutoff) = self.frequency_cutoff {
+            args.push("-f".into());
+            args.push(cutoff.to_string().into());
+        }
+        if let Some(threads) = self.threads {
+            args.push("-p".into());
+            args.push(threads.to_string().into());
+        }
+        args.push(self.corpus.clone().into_os_string());
+        args.push(self.model.clone().into_os_string());
+        args
+    }
+
+    /// Checks the options and that the inputs exist, so that missing files are reported as
+    /// [`ToolError::NotFound`](../tools/enum.ToolError.html#variant.NotFound).
+    pub fn validate(&self) -> Result<(), ToolError> {
+        fn invalid(message: &str) -> Result<(), ToolError> {
+            Err(ToolError::InvalidArgument(message.to_string()))
+        }
+
+        if self.c.is_some_and(|c| !(c > 0.0 && c.is_finite())) {
+            return invalid("C must be a positive number");
+        }
+        if self.eta.is_some_and(|eta| !(eta > 0.0 && eta.is_finite())) {
+            return invalid("eta must be a positive number");
+        }
+        if self.threads == Some(0) {
+            return invalid("the number of threads must be at least 1");
+        }
+        if self.max_iterations == Some(0) {
+            return invalid("the maximum number of iterations must be at least 1");
+        }
+        require_file(&self.corpus)?;
+        require_dir(&self.dicdir)?;
+        require_file(&self.dicdir.join("feature.def"))?;
+        if let Some(ref old_model) = self.old_model {
+            require_file(old_model)?;
+        }
+        require_parent_dir(&self.model)?;
+        Ok(())
+    }
+
+    /// Starts training in a child process.
+    pub fn spawn(&self) -> Result<Training, ToolError> {
+        self.spawn_with(|_| {})
+    }
+
+    /// Starts training in a child process, calling `on_progress` after each iteration.
+    pub fn spawn_with<F>(&self, mut on_progress: F) -> Result<Training, ToolError>
+    where
+        F: FnMut(&Progress) + Send + 'static,
+    {
+        self.validate()?;
+        let max_iterations = self.max_iterations;
+        let eta = self.eta.unwrap_or(DEFAULT_ETA);
+        let mut converging = 0;
+        let progress = Arc::new(Mutex::new(Vec::new()));
+        let exceeded = Arc::new(AtomicBool::new(false));
+        let observer = {
+            let progress = progress.clone();
+            let exceeded = exceeded.clone();
+            move |line: &str| match Progress::parse(line) {
+                Some(p) => {
+                    on_progress(&p);
+                    progress.lock().unwrap().push(p);
+                    // MeCab stops by itself after three iterations in a row below eta, so
+                    // only stop at the last iteration allowed if it doesn't.
+                    converging = if p.diff < eta { converging + 1 } else { 0 };
+                    let over = converging < CONVERGING_ITERATIONS
+                        && max_iterations.is_some_and(|max| p.iteration + 1 >= max);
+                    exceeded.store(over, Ordering::SeqCst);
+                    !over
+                }
+                None => true,
+            }
+        };
+        let process = ToolProcess::spawn_observed(Tool::CostTrain, self.args(), b"", observer)?;
+        Ok(Training {
+            process,
+            model: self.model.clone(),
+            progress,
+            exceeded,
+        })
+    }
+
+    /// Trains the model, blocking until it converges.
+    pub fn train(&self) -> Result<TrainingResult, TrainError> {
+        self.spawn()?.wait()
+    }
+}
+
+/// Training running in a child process, started by
+/// [`CostTrainer::spawn`](struct.CostTrainer.html#method.spawn).
+#[derive(Debug)]
+pub struct Training {
+    process: ToolProcess,
+    model: PathBuf,
+    progress: Arc<Mutex<Vec<Progress>>>,
+    exceeded: Arc<AtomicBool>,
+}
+
+impl Training {
+    /// Return a handle to cancel training from another thread.
+    pub fn kill_handle(&self) -> KillHandle {
+        self.process.kill_handle()
+    }
+
+    /// Cancels training. `wait` then returns
+    /// [`ToolError::Cancelled`](../tools/enum.ToolError.html#variant.Cancelled), unless
+    /// training had already finished.
+    pub fn kill(&self) -> io::Result<()> {
+        self.process.kill()
+    }
+
+    /// Return the statistics of the iterations so far.
+    pub fn progress(&self) -> Vec<Progress> {
+        self.progress.lock().unwrap().clone()
+    }
+
+    /// Waits for training to finish.
+    pub fn wait(self) -> Result<TrainingResult, TrainError> {
+        let result = self.process.wait();
+        let progress = self.progress.lock().unwrap().clone();
+        if self.exceeded.load(Ordering::SeqCst) {
+            if let Some(&last) = progress.last() {
+                return Err(TrainError::IterationLimit { last });
+            }
+        }
+        let output = result?.check()?;
+        require_file(&self.model)?;
+        Ok(TrainingResult {
+            model: self.model,
+            progress,
+            output,
+        })
+    }
+}
+
+/// The result of [`Retrainer::run`](struct.Retrainer.html#method.run).
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetrainResult {
+    /// The directory of the rebuilt dictionary.
+    pub dicdir: PathBuf,
+
+    pub training: TrainingResult,
+
+    /// The messages printed by `mecab-dict-gen`.
+    pub generated: ToolOutput,
+
+    /// The messages printed by `mecab-dict-index`.
+    pub compiled: ToolOutput,
+}
+
+/// Retrains a model and rebuilds the system dictionary with it.
+///
+/// This runs `mecab-cost-train`, `mecab-dict-gen` into the output directory, and then
+/// `mecab-dict-index` on the generated sources, so that the output directory can be used as
+/// `dicdir`.
+///
+/// ```rust,no_run
+/// use metalcab::train::{CostTrainer, Retrainer};
+///
+/// let trainer = CostTrainer::new("corpus.txt", "seed", "model").threads(4);
+/// let result = Retrainer::new(trainer, "final")
+///     .charset("utf-8")
+///     .run()
+///     .unwrap();
+/// println!("rebuilt {}", result.dicdir.display());
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Retrainer {
+    trainer: CostTrainer,
+    output_dir: PathBuf,
+    charset: Option<String>,
+}
+
+impl Retrainer {
+    pub fn new<P: AsRef<Path>>(trainer: CostTrainer, output_dir: P) -> Self {
+        Retrainer {
+            trainer,
+            output_dir: output_dir.as_ref().to_path_buf(),
+            charset: None,
+        }
+    }
+
+    /// Sets the charset of the generated sources and the compiled dictionary, which should
+    /// be the charset of the seed dictionary. MeCab defaults to EUC-JP.
+    pub fn charset(mut self, charset: &str) -> Self {
+        self.charset = Some(charset.to_string());
+        self
+    }
+
+    /// Return the generator run after training.
+    pub fn generator(&self) -> DictionaryGenerator {
+        DictionaryGenerator::new(
+            self.trainer.dicdir(),
+            self.trainer.model(),
+            &self.output_dir,
+        )
+    }
+
+    /// Runs all the steps.
+    pub fn run(&self) -> Result<RetrainResult, TrainError> {
+        self.run_with(|_| {})
+    }
+
+    /// Runs all the steps, calling `on_progress` after each iteration of training.
+    pub fn run_with<F>(&self, on_progress: F) -> Result<RetrainResult, TrainError>
+    where
+        F: FnMut(&Progress) + Send + 'static,
+    {
+        // Fail before training, which takes long.
+        self.trainer.validate()?;
+        require_dir(&self.output_dir)?;
+
+        let training = self.trainer.spawn_with(on_progress)?.wait()?;
+        let generator = self.generator();
+        let generated = generator.generate()?;
+        let mut compiler = generator.compiler();
+        if let Some(ref charset) = self.charset {
+            compiler = compiler.input_charset(charset).output_charset(charset);
+        }
+        let compiled = compiler.compile()?;
+        Ok(RetrainResult {
+            dicdir: self.output_dir.clone(),
+            training,
+            generated,
+            compiled,
+        })
+    }
+}